@@ -19,7 +19,8 @@ fn main() {
                                                   width,
                                                   num_colors,
                                                   &optimizer::KMeans,
-                                                  &ditherer::FloydSteinberg::checkered());
+                                                  &ditherer::FloydSteinberg::checkered())
+        .unwrap();
 
     png::save(&out_name, &palette, &out_image, width, height);
 }
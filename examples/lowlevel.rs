@@ -25,7 +25,7 @@ fn main() {
     let optimizer = optimizer::WeightedKMeans;
 
     println!("Generating palette");
-    let mut quantizer = Quantizer::new(&hist, &colorspace);
+    let mut quantizer = Quantizer::new(&hist, &colorspace).unwrap();
     let kmeans_step = (num_colors as f64).sqrt().round() as usize;
     while quantizer.num_colors() < num_colors {
         quantizer.step();
@@ -40,8 +40,8 @@ fn main() {
 
     println!("Remapping image to palette");
     let ditherer = ditherer::FloydSteinberg::checkered();
-    let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-    let image: Vec<_> = remapper.remap(&input_image, width);
+    let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+    let image: Vec<_> = remapper.remap(&input_image, width).unwrap();
 
     let (palette, image) = sort_palette(&palette, &image);
 
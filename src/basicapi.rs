@@ -1,69 +1,510 @@
+use std::collections::HashMap;
+
 use super::*;
 use optimizer::Optimizer;
-use ditherer::Ditherer;
+use ditherer::{Ditherer, PixelMode};
 
 /// A convenience function to simply quantize an image with sensible defaults.
 ///
+/// If the image has no more than `num_colors` distinct colors, these are returned unchanged as
+/// the palette and the image is remapped exactly, without dithering.
+///
+/// Returns an `Error` if `width` is zero, the palette would need more than 256 colors or the
+/// image contains colors that are invalid in the colorspace.
+///
 /// # Examples:
 /// ```
 /// # use exoquant::*;
 /// # let image = testdata::test_image();
 /// let (palette, indexed_data) = convert_to_indexed(&image.pixels, image.width, 256,
-///   &optimizer::KMeans, &ditherer::FloydSteinberg::new());
+///   &optimizer::KMeans, &ditherer::FloydSteinberg::new()).unwrap();
 /// ```
 pub fn convert_to_indexed<D, O>(image: &[Color],
                                 width: usize,
                                 num_colors: usize,
                                 optimizer: &O,
                                 ditherer: &D)
-                                -> (Vec<Color>, Vec<u8>)
+                                -> Result<(Vec<Color>, Vec<u8>), Error>
     where D: Ditherer,
           O: Optimizer
 {
-    let colorspace = SimpleColorSpace::default();
+    convert_to_indexed_with_colorspace(image,
+                                       width,
+                                       num_colors,
+                                       &SimpleColorSpace::default(),
+                                       optimizer,
+                                       ditherer)
+}
+
+/// Like `convert_to_indexed`, but quantizes in the given colorspace.
+///
+/// This is also how to quantize premultiplied alpha images: set the `alpha_mode` of the
+/// colorspace to `AlphaMode::Premultiplied` and the returned palette will be premultiplied as
+/// well.
+///
+/// # Examples:
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// let colorspace = SimpleColorSpace {
+///   alpha_mode: AlphaMode::Premultiplied,
+///   ..Default::default()
+/// };
+/// let (palette, indexed_data) = convert_to_indexed_with_colorspace(&image.pixels, image.width,
+///   256, &colorspace, &optimizer::KMeans, &ditherer::FloydSteinberg::new()).unwrap();
+/// ```
+pub fn convert_to_indexed_with_colorspace<C, D, O>(image: &[Color],
+                                                   width: usize,
+                                                   num_colors: usize,
+                                                   colorspace: &C,
+                                                   optimizer: &O,
+                                                   ditherer: &D)
+                                                   -> Result<(Vec<Color>, Vec<u8>), Error>
+    where C: ColorSpace,
+          D: Ditherer,
+          O: Optimizer
+{
+    if width == 0 {
+        return Err(Error::ZeroWidth);
+    }
 
     let hist = image.iter().cloned().collect();
 
-    let palette = generate_palette(&hist, &colorspace, optimizer, num_colors);
+    if let Some(palette) = exact_palette(&hist, num_colors) {
+        let image = remap_exact(image, &palette)?;
+        return Ok(sort_palette(&palette, &image));
+    }
+
+    let palette = generate_palette(&hist, colorspace, optimizer, num_colors)?;
 
-    let palette = optimizer.optimize_palette(&colorspace, &palette, &hist, 8);
+    let palette = optimizer.optimize_palette(colorspace, &palette, &hist, 8);
 
-    let image = Remapper::new(&palette, &colorspace, ditherer).remap(image, width);
+    let image = Remapper::new(&palette, colorspace, ditherer)?.remap(image, width)?;
 
-    sort_palette(&palette, &image)
+    Ok(sort_palette(&palette, &image))
 }
 
 /// A convenience function to just generate a palette from a historam with sensible defaults.
 ///
+/// If the histogram has no more than `num_colors` distinct colors, exactly these are returned.
+///
+/// Returns `Error::InvalidColor` if the histogram contains colors that are invalid in the
+/// colorspace.
+///
 /// # Examples:
 /// ```
 /// # use exoquant::*;
 /// # let image = testdata::test_image();
 /// # let histogram = image.pixels.iter().cloned().collect();
 /// let palette = generate_palette(&histogram, &SimpleColorSpace::default(), &optimizer::KMeans,
-///   256);
+///   256).unwrap();
 /// ```
 pub fn generate_palette<C, O>(hist: &Histogram,
                               colorspace: &C,
                               optimizer: &O,
                               num_colors: usize)
-                              -> Vec<Color>
+                              -> Result<Vec<Color>, Error>
     where C: ColorSpace,
           O: Optimizer
 {
-    let mut quantizer = Quantizer::new(hist, colorspace);
-    let kmeans_step = if num_colors > 64 {
+    if let Some(palette) = exact_palette(hist, num_colors) {
+        return Ok(palette);
+    }
+    let mut quantizer = Quantizer::new(hist, colorspace)?;
+    let kmeans_step = kmeans_step(num_colors);
+    while quantizer.num_colors() < num_colors {
+        quantizer.step();
+        if quantizer.num_colors() % kmeans_step == 0 {
+            quantizer = quantizer.optimize(optimizer, 4);
+        }
+    }
+    Ok(quantizer.colors(colorspace))
+}
+
+/// The quality a palette generated by `generate_palette_for_quality` has to reach.
+///
+/// Both variants are measured in the quantization colorspace, see `Quantizer::mse` and
+/// `Quantizer::psnr`.
+#[derive(Clone, Copy, Debug)]
+pub enum QualityTarget {
+    /// The maximum mean squared error.
+    MaxMse(f64),
+    /// The minimum peak signal-to-noise ratio in dB.
+    MinPsnr(f64),
+}
+
+impl QualityTarget {
+    fn max_mse(&self) -> f64 {
+        match *self {
+            QualityTarget::MaxMse(mse) => mse,
+            QualityTarget::MinPsnr(psnr) => 10f64.powf(-psnr / 10.0),
+        }
+    }
+}
+
+/// Generates the smallest palette that reaches a given quality target.
+///
+/// Quantization continues until either the `target` is met or the palette has `max_colors`
+/// entries.
+///
+/// # Examples:
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram = image.pixels.iter().cloned().collect();
+/// let palette = generate_palette_for_quality(&histogram, &SimpleColorSpace::default(),
+///   &optimizer::KMeans, QualityTarget::MinPsnr(30.0), 256).unwrap();
+/// assert!(palette.len() <= 256);
+/// ```
+pub fn generate_palette_for_quality<C, O>(hist: &Histogram,
+                                          colorspace: &C,
+                                          optimizer: &O,
+                                          target: QualityTarget,
+                                          max_colors: usize)
+                                          -> Result<Vec<Color>, Error>
+    where C: ColorSpace,
+          O: Optimizer
+{
+    let max_mse = target.max_mse();
+    let mut quantizer = Quantizer::new(hist, colorspace)?;
+    let kmeans_step = kmeans_step(max_colors);
+    while quantizer.num_colors() < max_colors && quantizer.mse() > max_mse {
+        quantizer.step();
+        if quantizer.num_colors() % kmeans_step == 0 {
+            quantizer = quantizer.optimize(optimizer, 4);
+        }
+    }
+    Ok(quantizer.colors(colorspace))
+}
+
+// Returns the colors of the histogram if there are no more than `num_colors` of them.
+fn exact_palette(hist: &Histogram, num_colors: usize) -> Option<Vec<Color>> {
+    if hist.len() > num_colors {
+        return None;
+    }
+    let mut palette: Vec<Color> = hist.iter().map(|(color, _)| color).collect();
+    palette.sort_by_key(|c| (c.r, c.g, c.b, c.a));
+    Some(palette)
+}
+
+// Remaps an image to a palette containing all of its colors.
+fn remap_exact(image: &[Color], palette: &[Color]) -> Result<Vec<u8>, Error> {
+    if palette.len() > 256 {
+        return Err(Error::TooManyColors(palette.len()));
+    }
+    let indices: HashMap<Color, u8> =
+        palette.iter().enumerate().map(|(i, &color)| (color, i as u8)).collect();
+    Ok(image.iter().map(|color| indices[color]).collect())
+}
+
+// How often to run K-Means optimizations during quantization to `num_colors`.
+fn kmeans_step(num_colors: usize) -> usize {
+    if num_colors > 64 {
         num_colors
     } else if num_colors <= 16 {
         1
     } else {
         (num_colors as f64).sqrt().round() as usize
+    }
+}
+
+/// Like `convert_to_indexed_with_colorspace`, but with binary (GIF-style) alpha.
+///
+/// Pixels with an alpha value below `alpha_threshold` become fully transparent, all others
+/// fully opaque, with premultiplied colors un-premultiplied. If there are any transparent pixels,
+/// exactly one palette entry is reserved for them and the remaining entries are quantized from
+/// the opaque pixels only. With a `num_colors` of 1 the palette then only holds the transparent
+/// entry.
+///
+/// # Examples:
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// let (palette, indexed_data) = convert_to_indexed_binary_alpha(&image.pixels, image.width,
+///   256, 128, &SimpleColorSpace::default(), &optimizer::KMeans,
+///   &ditherer::FloydSteinberg::new()).unwrap();
+/// ```
+pub fn convert_to_indexed_binary_alpha<C, D, O>(image: &[Color],
+                                                width: usize,
+                                                num_colors: usize,
+                                                alpha_threshold: u8,
+                                                colorspace: &C,
+                                                optimizer: &O,
+                                                ditherer: &D)
+                                                -> Result<(Vec<Color>, Vec<u8>), Error>
+    where C: ColorSpace,
+          D: Ditherer,
+          O: Optimizer
+{
+    if width == 0 {
+        return Err(Error::ZeroWidth);
+    }
+
+    let alpha_mode = colorspace.alpha_mode();
+    let image: Vec<Color> =
+        image.iter().map(|&c| threshold_alpha(c, alpha_threshold, alpha_mode)).collect();
+
+    let (hist, has_transparent) = split_transparent(image.iter().map(|&c| (c, 1.0)));
+
+    let num_opaque = num_opaque_colors(num_colors, has_transparent);
+    let exact = exact_palette(&hist, num_opaque);
+    let is_exact = exact.is_some();
+
+    let mut palette = match exact {
+        Some(palette) => palette,
+        None if num_opaque == 0 => Vec::new(),
+        None => {
+            let palette = generate_palette(&hist, colorspace, optimizer, num_opaque)?;
+            make_opaque(optimizer.optimize_palette(colorspace, &palette, &hist, 8))
+        }
     };
-    while quantizer.num_colors() < num_colors {
-        quantizer.step();
-        if quantizer.num_colors() % kmeans_step == 0 {
-            quantizer = quantizer.optimize(optimizer, 4);
+
+    let transparent_index = palette.len();
+    if has_transparent {
+        palette.push(Color::new(0, 0, 0, 0));
+    }
+    if palette.len() > 256 {
+        return Err(Error::TooManyColors(palette.len()));
+    }
+
+    // The transparent pixels are skipped by the ditherer and set to the reserved entry
+    // afterwards, so opaque pixels are only dithered against the opaque entries and no error is
+    // diffused across the alpha boundary.
+    let image = if is_exact {
+        remap_exact(&image, &palette)?
+    } else if transparent_index == 0 && has_transparent {
+        vec![0; image.len()]
+    } else {
+        let modes: Vec<PixelMode> = image.iter()
+            .map(|c| if c.a == 0 {
+                PixelMode::Skip
+            } else {
+                PixelMode::Dither
+            })
+            .collect();
+        let remapper = Remapper::new(&palette[..transparent_index], colorspace, ditherer)?;
+        let mut indexed = remapper.remap_with_modes(&image, width, &modes)?;
+        for (index, c) in indexed.iter_mut().zip(image.iter()) {
+            if c.a == 0 {
+                *index = transparent_index as u8;
+            }
+        }
+        indexed
+    };
+
+    Ok(sort_palette(&palette, &image))
+}
+
+/// Like `generate_palette`, but with binary (GIF-style) alpha.
+///
+/// The alpha values of the histogram colors are thresholded like in
+/// `convert_to_indexed_binary_alpha`. If any of them end up transparent, the last palette entry is
+/// reserved for `Color::new(0, 0, 0, 0)` and the transparent colors are left out of the
+/// quantization of the other entries. With a `num_colors` of 1 the palette then only holds the
+/// transparent entry.
+///
+/// # Examples:
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram = image.pixels.iter().cloned().collect();
+/// let palette = generate_palette_binary_alpha(&histogram, &SimpleColorSpace::default(),
+///   &optimizer::KMeans, 256, 128).unwrap();
+/// ```
+pub fn generate_palette_binary_alpha<C, O>(hist: &Histogram,
+                                           colorspace: &C,
+                                           optimizer: &O,
+                                           num_colors: usize,
+                                           alpha_threshold: u8)
+                                           -> Result<Vec<Color>, Error>
+    where C: ColorSpace,
+          O: Optimizer
+{
+    let (hist, has_transparent) =
+        split_transparent(hist.iter()
+            .map(|(color, count)| {
+                (threshold_alpha(color, alpha_threshold, colorspace.alpha_mode()), count)
+            }));
+    let num_opaque = num_opaque_colors(num_colors, has_transparent);
+    let mut palette = if num_opaque == 0 {
+        Vec::new()
+    } else {
+        make_opaque(generate_palette(&hist, colorspace, optimizer, num_opaque)?)
+    };
+    if has_transparent {
+        palette.push(Color::new(0, 0, 0, 0));
+    }
+    Ok(palette)
+}
+
+// Thresholds the alpha value of a color to either fully transparent or fully opaque.
+//
+// Colors with an alpha value below `threshold` become `Color::new(0, 0, 0, 0)`, all others
+// become opaque with their color channels kept (un-premultiplied if necessary).
+fn threshold_alpha(c: Color, threshold: u8, alpha_mode: AlphaMode) -> Color {
+    if c.a < threshold || c.a == 0 {
+        Color::new(0, 0, 0, 0)
+    } else if alpha_mode == AlphaMode::Premultiplied {
+        let a = c.a as u32;
+        let unpremultiply = |v: u8| ((v as u32 * 255 + a / 2) / a).min(255) as u8;
+        Color::new(unpremultiply(c.r), unpremultiply(c.g), unpremultiply(c.b), 255)
+    } else {
+        Color::new(c.r, c.g, c.b, 255)
+    }
+}
+
+// Returns a histogram of the opaque colors out of already thresholded colors and whether there
+// were any transparent ones.
+fn split_transparent<I>(colors: I) -> (Histogram, bool)
+    where I: IntoIterator<Item = (Color, f64)>
+{
+    let mut opaque = Histogram::new();
+    let mut has_transparent = false;
+    for (color, count) in colors {
+        if color.a == 0 {
+            has_transparent = true;
+        } else {
+            opaque.add_weighted(color, count);
+        }
+    }
+    (opaque, has_transparent)
+}
+
+// Sets the alpha value of palette colors quantized from opaque colors only back to fully opaque,
+// as the conversion from the quantization colorspace can round it down slightly.
+fn make_opaque(palette: Vec<Color>) -> Vec<Color> {
+    palette.into_iter().map(|c| Color::new(c.r, c.g, c.b, 255)).collect()
+}
+
+fn num_opaque_colors(num_colors: usize, has_transparent: bool) -> usize {
+    if has_transparent {
+        num_colors.saturating_sub(1)
+    } else {
+        num_colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn few_colors_are_passed_through_exactly() {
+        let colors: Vec<Color> = (0..12)
+            .map(|i| Color::new(i * 20 + 3, 255 - i * 7, i * i, 128 + i))
+            .collect();
+        let image: Vec<Color> = (0..1024).map(|i| colors[(i * 7 + i / 32) % 12]).collect();
+        let (palette, indexed) = convert_to_indexed(&image,
+                                                    32,
+                                                    16,
+                                                    &optimizer::KMeans,
+                                                    &ditherer::Ordered)
+            .unwrap();
+        assert_eq!(palette.len(), 12);
+        for (color, &index) in image.iter().zip(indexed.iter()) {
+            assert_eq!(*color, palette[index as usize]);
+        }
+        let histogram: Histogram = image.iter().cloned().collect();
+        let palette = generate_palette(&histogram,
+                                       &SimpleColorSpace::default(),
+                                       &optimizer::KMeans,
+                                       12)
+            .unwrap();
+        assert!(colors.iter().all(|c| palette.contains(c)));
+    }
+
+    #[test]
+    fn quality_target_limits_palette_size() {
+        let image = testdata::test_image();
+        let histogram: Histogram = image.pixels.iter().cloned().collect();
+        let colorspace = SimpleColorSpace::default();
+        let coarse = generate_palette_for_quality(&histogram,
+                                                  &colorspace,
+                                                  &optimizer::None,
+                                                  QualityTarget::MaxMse(0.001),
+                                                  256)
+            .unwrap();
+        let fine = generate_palette_for_quality(&histogram,
+                                                &colorspace,
+                                                &optimizer::None,
+                                                QualityTarget::MaxMse(0.0002),
+                                                256)
+            .unwrap();
+        assert!(coarse.len() < fine.len());
+        assert!(fine.len() < 256);
+        let mut quantizer = Quantizer::new(&histogram, &colorspace).unwrap();
+        while quantizer.num_colors() < coarse.len() - 1 {
+            quantizer.step();
+        }
+        assert!(quantizer.mse() > 0.001);
+    }
+
+    #[test]
+    fn binary_alpha_reserves_one_transparent_entry() {
+        let image: Vec<Color> = (0..4096)
+            .map(|i| Color::new((i & 63) as u8 * 4, (i >> 6) as u8 * 4, 128, (i & 255) as u8))
+            .collect();
+        let (palette, indexed) = convert_to_indexed_binary_alpha(&image,
+                                                                 64,
+                                                                 16,
+                                                                 128,
+                                                                 &SimpleColorSpace::default(),
+                                                                 &optimizer::KMeans,
+                                                                 &ditherer::FloydSteinberg::new())
+            .unwrap();
+        assert_eq!(palette.len(), 16);
+        assert_eq!(palette.iter().filter(|c| c.a == 0).count(), 1);
+        assert!(palette.iter().all(|c| c.a == 0 || c.a == 255));
+        for (color, &index) in image.iter().zip(indexed.iter()) {
+            assert_eq!(color.a < 128, palette[index as usize].a == 0);
+        }
+
+        let (palette, indexed) = convert_to_indexed_binary_alpha(&image,
+                                                                 64,
+                                                                 1,
+                                                                 128,
+                                                                 &SimpleColorSpace::default(),
+                                                                 &optimizer::KMeans,
+                                                                 &ditherer::FloydSteinberg::new())
+            .unwrap();
+        assert_eq!(palette, vec![Color::new(0, 0, 0, 0)]);
+        assert!(indexed.iter().all(|&i| i == 0));
+        let histogram: Histogram = image.iter().cloned().collect();
+        let palette = generate_palette_binary_alpha(&histogram,
+                                                    &SimpleColorSpace::default(),
+                                                    &optimizer::KMeans,
+                                                    1,
+                                                    128)
+            .unwrap();
+        assert_eq!(palette, vec![Color::new(0, 0, 0, 0)]);
+    }
+
+    #[test]
+    fn binary_alpha_does_not_dither_across_the_alpha_boundary() {
+        fn check<D: ditherer::Ditherer>(image: &[Color], num_colors: usize, ditherer: &D) {
+            let (palette, indexed) = convert_to_indexed_binary_alpha(image,
+                                                                     64,
+                                                                     num_colors,
+                                                                     128,
+                                                                     &SimpleColorSpace::default(),
+                                                                     &optimizer::KMeans,
+                                                                     ditherer)
+                .unwrap();
+            let transparent_index = palette.iter().position(|c| c.a == 0).unwrap();
+            for (color, &index) in image.iter().zip(indexed.iter()) {
+                assert_eq!(index as usize == transparent_index, color.a < 128);
+            }
+        }
+
+        let image: Vec<Color> = (0..4096)
+            .map(|i| {
+                let (x, y) = (i & 63, i >> 6);
+                Color::new(x as u8 * 4, y as u8 * 4, 200, ((x + y) * 2) as u8)
+            })
+            .collect();
+        for num_colors in 2..5 {
+            check(&image, num_colors, &ditherer::Ordered);
+            check(&image, num_colors, &ditherer::FloydSteinberg::new());
         }
     }
-    quantizer.colors(colorspace)
 }
@@ -1,7 +1,7 @@
 use std::ops::{Add, AddAssign, Sub, Mul, MulAssign, Div};
 
 /// A RGBA8 color used for both the input image data and the palette output.
-#[derive(Copy,Clone,Eq,PartialEq,Hash)]
+#[derive(Copy,Clone,Eq,PartialEq,Hash,Debug)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -56,6 +56,11 @@ impl Colorf {
         self.dot(self).sqrt()
     }
 
+    /// Returns whether all channel components are neither NaN nor infinite.
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite() && self.a.is_finite()
+    }
+
     /// Returns a new `Colorf` with each color component raised to the given power.
     pub fn pow(&self, e: f64) -> Colorf {
         Colorf {
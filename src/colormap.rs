@@ -1,11 +1,14 @@
+use std::cmp::Ordering;
+
 use super::*;
 
 /// A data structure for fast nearest color lookups in a palette.
 pub struct ColorMap {
-    kdtree: KDNode,
+    kdtree: Option<KDNode>,
     neighbor_distance: Vec<f64>,
     neighbors: Vec<Vec<usize>>,
     colors: Vec<Colorf>,
+    ciede2000: Option<(LabColorSpace, usize)>,
 }
 
 struct KDNode {
@@ -16,14 +19,66 @@ struct KDNode {
     right: Option<Box<KDNode>>,
 }
 
+#[derive(Clone, Copy)]
 struct KDNearest {
     index: usize,
     distance: f64,
 }
 
+// The maximum number of candidates `ColorMap::with_ciede2000` looks up per color.
+const MAX_CIEDE2000_CANDIDATES: usize = 16;
+
+// The k nearest colors found so far, sorted by distance. Kept in a fixed-size array so a
+// CIEDE2000 lookup doesn't allocate.
+struct KNearest {
+    entries: [KDNearest; MAX_CIEDE2000_CANDIDATES],
+    len: usize,
+    k: usize,
+}
+
+impl KNearest {
+    fn new(k: usize) -> KNearest {
+        KNearest {
+            entries: [KDNearest {
+                index: 0,
+                distance: f64::MAX,
+            }; MAX_CIEDE2000_CANDIDATES],
+            len: 0,
+            k: k.min(MAX_CIEDE2000_CANDIDATES),
+        }
+    }
+
+    fn limit(&self) -> f64 {
+        if self.len < self.k {
+            f64::MAX
+        } else {
+            self.entries[self.k - 1].distance
+        }
+    }
+
+    fn insert(&mut self, nearest: KDNearest) {
+        let pos = self.entries[..self.len]
+            .iter()
+            .position(|n| n.distance > nearest.distance)
+            .unwrap_or(self.len);
+        if pos >= self.k {
+            return;
+        }
+        let end = self.len.min(self.k - 1);
+        for i in (pos..end).rev() {
+            self.entries[i + 1] = self.entries[i];
+        }
+        self.entries[pos] = nearest;
+        self.len = (self.len + 1).min(self.k);
+    }
+
+    fn as_slice(&self) -> &[KDNearest] {
+        &self.entries[..self.len]
+    }
+}
+
 impl KDNode {
     fn new(mut indices: Vec<usize>, colors: &[Colorf]) -> KDNode {
-        assert!(indices.len() > 0);
         let mut sum = Colorf::zero();
         let mut sum2 = Colorf::zero();
         for i in &indices {
@@ -62,7 +117,7 @@ impl KDNode {
             }
         };
         indices.sort_by(|a, b| {
-            colors[*a].dot(&normal).partial_cmp(&colors[*b].dot(&normal)).unwrap()
+            colors[*a].dot(&normal).partial_cmp(&colors[*b].dot(&normal)).unwrap_or(Ordering::Equal)
         });
         let i = indices.len() / 2;
         let left = if i > 0 {
@@ -137,6 +192,34 @@ impl KDNode {
 
         result
     }
+
+    // Collects the `k` nearest colors to `needle` into `result`, sorted by distance.
+    fn find_k_nearest(&self, needle: Colorf, result: &mut KNearest) {
+        let diff = needle - self.mid_point;
+        let distance = diff.dot(&diff).sqrt();
+
+        if distance < result.limit() {
+            result.insert(KDNearest {
+                index: self.index,
+                distance: distance,
+            });
+        }
+
+        let dot = diff.dot(&self.normal);
+        let (near, far) = if dot <= 0.0 {
+            (&self.left, &self.right)
+        } else {
+            (&self.right, &self.left)
+        };
+        if let Some(ref near) = *near {
+            near.find_k_nearest(needle, result);
+        }
+        if dot.abs() < result.limit() {
+            if let Some(ref far) = *far {
+                far.find_k_nearest(needle, result);
+            }
+        }
+    }
 }
 
 fn occludes(origin: Colorf, occluder: Colorf, target: Colorf) -> bool {
@@ -152,12 +235,19 @@ impl ColorMap {
     }
 
     /// Create a `ColorMap` from float colors.
+    ///
+    /// An empty palette results in a `ColorMap` that always returns index 0.
     pub fn from_float_colors(colors: Vec<Colorf>) -> ColorMap {
-        let kdtree = KDNode::new((0..colors.len()).collect(), &colors);
+        let kdtree = if colors.is_empty() {
+            None
+        } else {
+            Some(KDNode::new((0..colors.len()).collect(), &colors))
+        };
         let neighbor_distance = colors.iter()
             .enumerate()
             .map(|(i, c)| {
-                if let Some(nearest) = kdtree.find_nearest(*c, ::std::f64::MAX, i) {
+                let nearest = kdtree.as_ref().and_then(|t| t.find_nearest(*c, ::std::f64::MAX, i));
+                if let Some(nearest) = nearest {
                     nearest.distance
                 } else {
                     ::std::f64::MAX
@@ -182,12 +272,53 @@ impl ColorMap {
             neighbor_distance: neighbor_distance,
             neighbors: neighbors,
             colors: colors,
+            ciede2000: None,
         }
     }
 
+    /// Enables a CIEDE2000 refinement step in `find_nearest`.
+    ///
+    /// The `num_candidates` nearest palette colors by Euclidean distance are looked up first,
+    /// then the one with the smallest `LabColorSpace::distance` is returned. The palette
+    /// colors of this `ColorMap` need to be in the given colorspace. `num_candidates` is clamped
+    /// to the range 1 - 16.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// let colorspace = LabColorSpace::default();
+    /// let palette = [Color::new(0, 0, 255, 255), Color::new(90, 0, 255, 255)];
+    /// let map = ColorMap::new(&palette, &colorspace).with_ciede2000(&colorspace, 4);
+    /// let index = map.find_nearest(colorspace.to_float(Color::new(40, 20, 240, 255)));
+    /// ```
+    pub fn with_ciede2000(mut self, colorspace: &LabColorSpace, num_candidates: usize) -> ColorMap {
+        let num_candidates = num_candidates.clamp(1, MAX_CIEDE2000_CANDIDATES);
+        self.ciede2000 = Some((colorspace.clone(), num_candidates));
+        self
+    }
+
     /// Returns the index of the nearest color in the palette.
     pub fn find_nearest(&self, color: Colorf) -> usize {
-        if let Some(nearest) = self.kdtree.find_nearest(color, ::std::f64::MAX, ::std::usize::MAX) {
+        if let Some((ref colorspace, num_candidates)) = self.ciede2000 {
+            let mut candidates = KNearest::new(num_candidates);
+            if let Some(ref kdtree) = self.kdtree {
+                kdtree.find_k_nearest(color, &mut candidates);
+            }
+            let mut best_index = 0;
+            let mut best_distance = f64::MAX;
+            for candidate in candidates.as_slice() {
+                let distance = colorspace.distance(color, self.colors[candidate.index]);
+                if distance < best_distance {
+                    best_index = candidate.index;
+                    best_distance = distance;
+                }
+            }
+            return best_index;
+        }
+        let nearest = self.kdtree
+            .as_ref()
+            .and_then(|t| t.find_nearest(color, ::std::f64::MAX, ::std::usize::MAX));
+        if let Some(nearest) = nearest {
             nearest.index
         } else {
             0
@@ -214,3 +345,40 @@ impl ColorMap {
         self.colors.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::{KNearest, MAX_CIEDE2000_CANDIDATES};
+
+    #[test]
+    fn k_nearest_matches_brute_force() {
+        let colors: Vec<Colorf> = (0..50)
+            .map(|i| {
+                Colorf {
+                    r: ((i * 37) % 101) as f64 / 100.0,
+                    g: ((i * 59) % 103) as f64 / 102.0,
+                    b: ((i * 13) % 97) as f64 / 96.0,
+                    a: ((i * 7) % 5) as f64 / 4.0,
+                }
+            })
+            .collect();
+        let map = ColorMap::from_float_colors(colors.clone());
+        let needle = Colorf {
+            r: 0.3,
+            g: 0.6,
+            b: 0.2,
+            a: 0.5,
+        };
+        let mut expected: Vec<f64> = colors.iter().map(|&c| (c - needle).abs()).collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for &k in &[1, 5, 16, 40] {
+            let mut result = KNearest::new(k);
+            map.kdtree.as_ref().unwrap().find_k_nearest(needle, &mut result);
+            assert_eq!(result.as_slice().len(), k.min(MAX_CIEDE2000_CANDIDATES));
+            for (nearest, distance) in result.as_slice().iter().zip(expected.iter()) {
+                assert!((nearest.distance - distance).abs() < 1e-12);
+            }
+        }
+    }
+}
@@ -1,5 +1,21 @@
 use super::*;
 
+/// The alpha convention of the `Color` buffers a `ColorSpace` converts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlphaMode {
+    /// The color channels are independent of the alpha channel.
+    Straight,
+    /// The color channels are already multiplied by the alpha value.
+    ///
+    /// Input colors are un-premultiplied in floating point before quantization, so no
+    /// precision is lost in low alpha pixels, and the palette is premultiplied again on output.
+    ///
+    /// This happens in `ColorSpace::to_float` and `ColorSpace::from_float`, so `to_linear`,
+    /// `from_linear` and the alpha folding controlled by `transparency_scale` always work on
+    /// straight colors and don't depend on the alpha mode.
+    Premultiplied,
+}
+
 /// Defines the colorspaces in which to do quantization and remapping
 ///
 /// The current implementation is subject to change. Just use
@@ -10,6 +26,11 @@ pub trait ColorSpace {
     fn to_linear(&self, color: Colorf) -> Colorf;
     fn from_linear(&self, color: Colorf) -> Colorf;
 
+    /// Returns the alpha convention of both the input image and palette colors.
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Straight
+    }
+
     fn to_dither(&self, c: Colorf) -> Colorf {
         c
     }
@@ -18,15 +39,22 @@ pub trait ColorSpace {
     }
 
     fn to_float(&self, c: Color) -> Colorf {
-        self.to_linear(Colorf {
+        let color = Colorf {
             r: c.r as f64 / 255.0,
             g: c.g as f64 / 255.0,
             b: c.b as f64 / 255.0,
             a: c.a as f64 / 255.0,
-        })
+        };
+        self.to_linear(straight_alpha(color, self.alpha_mode()))
     }
     fn from_float(&self, c: Colorf) -> Color {
-        let c = self.from_linear(c);
+        let mut c = self.from_linear(c);
+        if self.alpha_mode() == AlphaMode::Premultiplied {
+            let a = c.a.clamp(0.0, 1.0);
+            c.r *= a;
+            c.g *= a;
+            c.b *= a;
+        }
         Color::new((c.r * 255.0).max(0.0).min(255.0) as u8,
                    (c.g * 255.0).max(0.0).min(255.0) as u8,
                    (c.b * 255.0).max(0.0).min(255.0) as u8,
@@ -34,6 +62,98 @@ pub trait ColorSpace {
     }
 }
 
+/// Un-premultiplies a color with channels in the 0.0 - 1.0 range if `alpha_mode` says it is
+/// premultiplied.
+pub fn straight_alpha(mut color: Colorf, alpha_mode: AlphaMode) -> Colorf {
+    if alpha_mode == AlphaMode::Premultiplied && color.a > 0.0 {
+        let f = 1.0 / color.a;
+        color.r *= f;
+        color.g *= f;
+        color.b *= f;
+    }
+    color
+}
+
+/// Decodes a single sRGB encoded channel value to linear light using the exact
+/// IEC 61966-2-1 transfer function.
+fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a single linear light channel value to sRGB, the inverse of `srgb_to_linear`.
+fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v.max(0.0) * 12.92
+    } else {
+        v.powf(1.0 / 2.4) * 1.055 - 0.055
+    }
+}
+
+fn srgb_decode(c: Colorf) -> Colorf {
+    Colorf {
+        r: srgb_to_linear(c.r),
+        g: srgb_to_linear(c.g),
+        b: srgb_to_linear(c.b),
+        a: c.a,
+    }
+}
+
+fn srgb_encode(c: Colorf) -> Colorf {
+    Colorf {
+        r: linear_to_srgb(c.r),
+        g: linear_to_srgb(c.g),
+        b: linear_to_srgb(c.b),
+        a: c.a,
+    }
+}
+
+/// Applies the channel scale and folds the alpha value into the color channels the same way
+/// `SimpleColorSpace::to_linear` does.
+fn fold_alpha(color: Colorf, scale: Colorf, transparency_scale: f64) -> Colorf {
+    let mut c = color * scale;
+    let f = c.a * (1.0 - transparency_scale) + transparency_scale;
+    c.r *= f;
+    c.g *= f;
+    c.b *= f;
+    c
+}
+
+/// The inverse of `fold_alpha`.
+fn unfold_alpha(color: Colorf, scale: Colorf, transparency_scale: f64) -> Colorf {
+    let mut c = color;
+    let f = 1.0 / (c.a * (1.0 - transparency_scale) + transparency_scale);
+    c.r *= f;
+    c.g *= f;
+    c.b *= f;
+    c / scale
+}
+
+// The scale used for the linear light dither space of the perceptual colorspaces.
+fn alpha_scale(scale: Colorf) -> Colorf {
+    Colorf {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+        a: scale.a,
+    }
+}
+
+/// The transfer function `SimpleColorSpace` assumes for the input colors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransferFunction {
+    /// Input values are treated as pure power-law encoded: quantization happens on the
+    /// input values raised to `gamma`, dithering on the input values raised to `dither_gamma`.
+    Gamma,
+    /// Input values are decoded to linear light using the exact piecewise sRGB transfer function
+    /// (IEC 61966-2-1). Dithering happens in this linear light, quantization on the linear
+    /// values raised to `gamma / dither_gamma`.
+    Srgb,
+}
+
 /// The default colorspace implementation.
 
 pub struct SimpleColorSpace {
@@ -41,6 +161,8 @@ pub struct SimpleColorSpace {
     pub dither_gamma: f64,
     pub transparency_scale: f64,
     pub scale: Colorf,
+    pub transfer: TransferFunction,
+    pub alpha_mode: AlphaMode,
 }
 
 impl Default for SimpleColorSpace {
@@ -55,13 +177,45 @@ impl Default for SimpleColorSpace {
                 b: 0.8,
                 a: 0.75,
             },
+            transfer: TransferFunction::Gamma,
+            alpha_mode: AlphaMode::Straight,
         }
     }
 }
 
+impl SimpleColorSpace {
+    /// Returns a `SimpleColorSpace` using the exact sRGB transfer function.
+    ///
+    /// Error diffusion dithering then happens in correct linear light, which avoids dark
+    /// gradients coming out too bright.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// let colorspace = SimpleColorSpace::srgb();
+    /// let palette = generate_palette(&histogram, &colorspace, &optimizer::KMeans, 16).unwrap();
+    /// let ditherer = ditherer::FloydSteinberg::new();
+    /// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+    /// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+    /// ```
+    pub fn srgb() -> SimpleColorSpace {
+        SimpleColorSpace { transfer: TransferFunction::Srgb, ..Default::default() }
+    }
+}
+
 impl ColorSpace for SimpleColorSpace {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
     fn to_linear(&self, color: Colorf) -> Colorf {
-        let mut color = color.pow(self.gamma) * self.scale;
+        let color = match self.transfer {
+            TransferFunction::Gamma => color.pow(self.gamma),
+            TransferFunction::Srgb => srgb_decode(color).pow(self.gamma / self.dither_gamma),
+        };
+        let mut color = color * self.scale;
         let f = color.a * (1.0 - self.transparency_scale) + self.transparency_scale;
         color.r *= f;
         color.g *= f;
@@ -70,14 +224,12 @@ impl ColorSpace for SimpleColorSpace {
     }
 
     fn from_linear(&self, color: Colorf) -> Colorf {
-        let c = color / self.scale;
-        let g = 1.0 / self.gamma;
-        let mut c = c.pow(g);
-        let f = 1.0 / (color.a * (1.0 - self.transparency_scale) + self.transparency_scale);
-        c.r *= f;
-        c.g *= f;
-        c.b *= f;
-        c
+        // the alpha folding has to be undone before the gamma to get an exact inverse
+        let c = unfold_alpha(color, self.scale, self.transparency_scale);
+        match self.transfer {
+            TransferFunction::Gamma => c.pow(1.0 / self.gamma),
+            TransferFunction::Srgb => srgb_encode(c.pow(self.dither_gamma / self.gamma)),
+        }
     }
 
     fn to_dither(&self, color: Colorf) -> Colorf {
@@ -88,3 +240,466 @@ impl ColorSpace for SimpleColorSpace {
         color.pow(self.gamma / self.dither_gamma)
     }
 }
+
+/// A perceptual colorspace based on Björn Ottosson's Oklab.
+///
+/// Input colors are decoded from sRGB to linear light using the exact sRGB transfer function
+/// and then transformed to Oklab, with the `L`, `a` and `b` components stored in the `r`, `g` and
+/// `b` channels of the `Colorf`. Euclidean distances in this space closely follow perceived
+/// color differences, which avoids the hue shifts `SimpleColorSpace` can produce in saturated
+/// blues and purples.
+///
+/// Alpha is folded into the color channels the same way `SimpleColorSpace` does it, so that
+/// differences between (nearly) transparent colors count for less. Dithering is done in linear
+/// light.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// let colorspace = OklabColorSpace::default();
+/// let palette = generate_palette(&histogram, &colorspace, &optimizer::KMeans, 16).unwrap();
+/// let ditherer = ditherer::FloydSteinberg::new();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+pub struct OklabColorSpace {
+    pub transparency_scale: f64,
+    pub scale: Colorf,
+    pub alpha_mode: AlphaMode,
+}
+
+impl Default for OklabColorSpace {
+    fn default() -> OklabColorSpace {
+        OklabColorSpace {
+            transparency_scale: 0.01,
+            scale: Colorf {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a: 0.75,
+            },
+            alpha_mode: AlphaMode::Straight,
+        }
+    }
+}
+
+impl OklabColorSpace {
+    fn linear_to_oklab(c: Colorf) -> Colorf {
+        let l = (0.4122214708 * c.r + 0.5363325363 * c.g + 0.0514459929 * c.b).cbrt();
+        let m = (0.2119034982 * c.r + 0.6806995451 * c.g + 0.1073969566 * c.b).cbrt();
+        let s = (0.0883024619 * c.r + 0.2817188376 * c.g + 0.6299787005 * c.b).cbrt();
+        Colorf {
+            r: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            g: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+            a: c.a,
+        }
+    }
+
+    fn oklab_to_linear(c: Colorf) -> Colorf {
+        let l = c.r + 0.3963377774 * c.g + 0.2158037573 * c.b;
+        let m = c.r - 0.1055613458 * c.g - 0.0638541728 * c.b;
+        let s = c.r - 0.0894841775 * c.g - 1.2914855480 * c.b;
+        let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+        Colorf {
+            r: 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            g: -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            b: -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+            a: c.a,
+        }
+    }
+}
+
+impl ColorSpace for OklabColorSpace {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn to_linear(&self, color: Colorf) -> Colorf {
+        let oklab = Self::linear_to_oklab(srgb_decode(color));
+        fold_alpha(oklab, self.scale, self.transparency_scale)
+    }
+
+    fn from_linear(&self, color: Colorf) -> Colorf {
+        let oklab = unfold_alpha(color, self.scale, self.transparency_scale);
+        srgb_encode(Self::oklab_to_linear(oklab))
+    }
+
+    fn to_dither(&self, color: Colorf) -> Colorf {
+        let oklab = unfold_alpha(color, self.scale, self.transparency_scale);
+        fold_alpha(Self::oklab_to_linear(oklab),
+                   alpha_scale(self.scale),
+                   self.transparency_scale)
+    }
+
+    fn from_dither(&self, color: Colorf) -> Colorf {
+        let linear = unfold_alpha(color, alpha_scale(self.scale), self.transparency_scale);
+        fold_alpha(Self::linear_to_oklab(linear),
+                   self.scale,
+                   self.transparency_scale)
+    }
+}
+
+/// A CIE L\*a\*b\* colorspace with a configurable reference white.
+///
+/// Input colors are decoded from sRGB to linear light, converted to CIE XYZ and, if the
+/// `white_point` differs from the D65 white of sRGB, chromatically adapted to it using the
+/// Bradford transform. The resulting `L*`, `a*` and `b*` components are divided by 100 and stored
+/// in the `r`, `g` and `b` channels of the `Colorf`, so the Euclidean distance in this space is
+/// the CIE76 ΔE / 100. Alpha is folded in the same way as in `SimpleColorSpace`.
+///
+/// Use `ColorMap::with_ciede2000` to pick palette colors by the CIEDE2000 color difference
+/// instead.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// let colorspace = LabColorSpace::with_white_point(LabColorSpace::D50);
+/// let palette = generate_palette(&histogram, &colorspace, &optimizer::KMeans, 16).unwrap();
+/// let map = ColorMap::new(&palette, &colorspace).with_ciede2000(&colorspace, 4);
+/// let ditherer = ditherer::FloydSteinberg::new();
+/// let remapper = Remapper::from_color_map(map, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct LabColorSpace {
+    /// The reference white as CIE XYZ with `Y` normalized to 1.
+    pub white_point: [f64; 3],
+    pub transparency_scale: f64,
+    pub scale: Colorf,
+    pub alpha_mode: AlphaMode,
+}
+
+const SRGB_TO_XYZ: [[f64; 3]; 3] = [[0.4124564, 0.3575761, 0.1804375],
+                                    [0.2126729, 0.7151522, 0.0721750],
+                                    [0.0193339, 0.1191920, 0.9503041]];
+const XYZ_TO_SRGB: [[f64; 3]; 3] = [[3.2404542, -1.5371385, -0.4985314],
+                                    [-0.9692660, 1.8760108, 0.0415560],
+                                    [0.0556434, -0.2040259, 1.0572252]];
+const BRADFORD: [[f64; 3]; 3] = [[0.8951, 0.2664, -0.1614],
+                                 [-0.7502, 1.7135, 0.0367],
+                                 [0.0389, -0.0685, 1.0296]];
+const BRADFORD_INV: [[f64; 3]; 3] = [[0.9869929, -0.1470543, 0.1599627],
+                                     [0.4323053, 0.5183603, 0.0492912],
+                                     [-0.0085287, 0.0400428, 0.9684867]];
+
+fn mul3(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    [m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+     m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+     m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2]]
+}
+
+fn lab_f(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inv(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA {
+        t * t * t
+    } else {
+        3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+impl Default for LabColorSpace {
+    fn default() -> LabColorSpace {
+        LabColorSpace::with_white_point(LabColorSpace::D65)
+    }
+}
+
+impl LabColorSpace {
+    /// The CIE standard illuminant D65, the reference white of sRGB.
+    pub const D65: [f64; 3] = [0.95047, 1.0, 1.08883];
+    /// The CIE standard illuminant D50, commonly used in print workflows.
+    pub const D50: [f64; 3] = [0.96422, 1.0, 0.82521];
+
+    /// Returns a `LabColorSpace` with the given reference white and default scaling.
+    pub fn with_white_point(white_point: [f64; 3]) -> LabColorSpace {
+        LabColorSpace {
+            white_point: white_point,
+            transparency_scale: 0.01,
+            scale: Colorf {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a: 0.75,
+            },
+            alpha_mode: AlphaMode::Straight,
+        }
+    }
+
+    // Per-cone scale factors of the Bradford adaptation from D65 to the white point.
+    fn adaptation(&self) -> [f64; 3] {
+        let src = mul3(&BRADFORD, Self::D65);
+        let dst = mul3(&BRADFORD, self.white_point);
+        [dst[0] / src[0], dst[1] / src[1], dst[2] / src[2]]
+    }
+
+    fn linear_to_lab(&self, c: Colorf) -> Colorf {
+        let xyz = mul3(&SRGB_TO_XYZ, [c.r, c.g, c.b]);
+        let cone = mul3(&BRADFORD, xyz);
+        let k = self.adaptation();
+        let xyz = mul3(&BRADFORD_INV, [cone[0] * k[0], cone[1] * k[1], cone[2] * k[2]]);
+        let fx = lab_f(xyz[0] / self.white_point[0]);
+        let fy = lab_f(xyz[1] / self.white_point[1]);
+        let fz = lab_f(xyz[2] / self.white_point[2]);
+        Colorf {
+            r: 1.16 * fy - 0.16,
+            g: 5.0 * (fx - fy),
+            b: 2.0 * (fy - fz),
+            a: c.a,
+        }
+    }
+
+    fn lab_to_linear(&self, c: Colorf) -> Colorf {
+        let fy = (c.r + 0.16) / 1.16;
+        let fx = fy + c.g / 5.0;
+        let fz = fy - c.b / 2.0;
+        let xyz = [lab_f_inv(fx) * self.white_point[0],
+                   lab_f_inv(fy) * self.white_point[1],
+                   lab_f_inv(fz) * self.white_point[2]];
+        let cone = mul3(&BRADFORD, xyz);
+        let k = self.adaptation();
+        let xyz = mul3(&BRADFORD_INV, [cone[0] / k[0], cone[1] / k[1], cone[2] / k[2]]);
+        let rgb = mul3(&XYZ_TO_SRGB, xyz);
+        Colorf {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+            a: c.a,
+        }
+    }
+
+    /// Returns the CIEDE2000 based distance between two colors in this colorspace.
+    ///
+    /// The color difference is ΔE2000 / 100, weighted by the alpha value of `a` the same way
+    /// the Euclidean distance in this colorspace is. The difference in (scaled) alpha is added
+    /// in quadrature.
+    pub fn distance(&self, a: Colorf, b: Colorf) -> f64 {
+        let f = a.a * (1.0 - self.transparency_scale) + self.transparency_scale;
+        let lab1 = unfold_alpha(a, self.scale, self.transparency_scale) * 100.0;
+        let lab2 = unfold_alpha(b, self.scale, self.transparency_scale) * 100.0;
+        let de = ciede2000(lab1, lab2) * 0.01 * f;
+        let da = a.a - b.a;
+        (de * de + da * da).sqrt()
+    }
+}
+
+/// The CIEDE2000 color difference of two colors with `L*`, `a*` and `b*` stored in the `r`, `g`
+/// and `b` channels.
+fn ciede2000(c1: Colorf, c2: Colorf) -> f64 {
+    use std::f64::consts::PI;
+    let deg = PI / 180.0;
+    let pow25_7 = 25f64.powi(7);
+
+    let cbar = ((c1.g * c1.g + c1.b * c1.b).sqrt() + (c2.g * c2.g + c2.b * c2.b).sqrt()) * 0.5;
+    let g = 0.5 * (1.0 - (cbar.powi(7) / (cbar.powi(7) + pow25_7)).sqrt());
+    let a1 = (1.0 + g) * c1.g;
+    let a2 = (1.0 + g) * c2.g;
+    let cp1 = (a1 * a1 + c1.b * c1.b).sqrt();
+    let cp2 = (a2 * a2 + c2.b * c2.b).sqrt();
+    let hue = |a: f64, b: f64| if a == 0.0 && b == 0.0 {
+        0.0
+    } else {
+        let h = b.atan2(a) / deg;
+        if h < 0.0 { h + 360.0 } else { h }
+    };
+    let hp1 = hue(a1, c1.b);
+    let hp2 = hue(a2, c2.b);
+
+    let dl = c2.r - c1.r;
+    let dc = cp2 - cp1;
+    let dh = if cp1 * cp2 == 0.0 {
+        0.0
+    } else {
+        let d = hp2 - hp1;
+        if d > 180.0 {
+            d - 360.0
+        } else if d < -180.0 {
+            d + 360.0
+        } else {
+            d
+        }
+    };
+    let dh = 2.0 * (cp1 * cp2).sqrt() * (dh * 0.5 * deg).sin();
+
+    let lbar = (c1.r + c2.r) * 0.5;
+    let cbarp = (cp1 + cp2) * 0.5;
+    let hbar = if cp1 * cp2 == 0.0 {
+        hp1 + hp2
+    } else if (hp1 - hp2).abs() <= 180.0 {
+        (hp1 + hp2) * 0.5
+    } else if hp1 + hp2 < 360.0 {
+        (hp1 + hp2 + 360.0) * 0.5
+    } else {
+        (hp1 + hp2 - 360.0) * 0.5
+    };
+    let t = 1.0 - 0.17 * ((hbar - 30.0) * deg).cos() + 0.24 * (2.0 * hbar * deg).cos() +
+            0.32 * ((3.0 * hbar + 6.0) * deg).cos() -
+            0.20 * ((4.0 * hbar - 63.0) * deg).cos();
+    let dtheta = 30.0 * (-((hbar - 275.0) / 25.0).powi(2)).exp();
+    let rc = 2.0 * (cbarp.powi(7) / (cbarp.powi(7) + pow25_7)).sqrt();
+    let l50 = (lbar - 50.0) * (lbar - 50.0);
+    let sl = 1.0 + 0.015 * l50 / (20.0 + l50).sqrt();
+    let sc = 1.0 + 0.045 * cbarp;
+    let sh = 1.0 + 0.015 * cbarp * t;
+    let rt = -(2.0 * dtheta * deg).sin() * rc;
+
+    let dl = dl / sl;
+    let dc = dc / sc;
+    let dh = dh / sh;
+    (dl * dl + dc * dc + dh * dh + rt * dc * dh).sqrt()
+}
+
+impl ColorSpace for LabColorSpace {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn to_linear(&self, color: Colorf) -> Colorf {
+        let lab = self.linear_to_lab(srgb_decode(color));
+        fold_alpha(lab, self.scale, self.transparency_scale)
+    }
+
+    fn from_linear(&self, color: Colorf) -> Colorf {
+        let lab = unfold_alpha(color, self.scale, self.transparency_scale);
+        srgb_encode(self.lab_to_linear(lab))
+    }
+
+    fn to_dither(&self, color: Colorf) -> Colorf {
+        let lab = unfold_alpha(color, self.scale, self.transparency_scale);
+        fold_alpha(self.lab_to_linear(lab),
+                   alpha_scale(self.scale),
+                   self.transparency_scale)
+    }
+
+    fn from_dither(&self, color: Colorf) -> Colorf {
+        let linear = unfold_alpha(color, alpha_scale(self.scale), self.transparency_scale);
+        fold_alpha(self.linear_to_lab(linear),
+                   self.scale,
+                   self.transparency_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn check_round_trip<T: ColorSpace>(colorspace: &T) {
+        for &color in &[Color::new(0, 0, 0, 255),
+                        Color::new(255, 255, 255, 255),
+                        Color::new(30, 20, 220, 255),
+                        Color::new(128, 0, 200, 128),
+                        Color::new(250, 140, 10, 3)] {
+            let c = colorspace.to_float(color);
+            let d = colorspace.from_dither(colorspace.to_dither(c));
+            assert!((c - d).abs() < 1e-6);
+            let back = colorspace.from_linear(c);
+            assert!((back.r * 255.0 - color.r as f64).abs() < 1e-3);
+            assert!((back.g * 255.0 - color.g as f64).abs() < 1e-3);
+            assert!((back.b * 255.0 - color.b as f64).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn premultiplied_alpha() {
+        let straight = SimpleColorSpace::default();
+        let premultiplied = SimpleColorSpace {
+            alpha_mode: AlphaMode::Premultiplied,
+            ..Default::default()
+        };
+        let c1 = straight.to_float(Color::new(200, 100, 50, 128));
+        let c2 = premultiplied.to_float(Color::new(100, 50, 25, 128));
+        assert!((c1 - c2).abs() < 1e-2);
+        let c = premultiplied.to_float(Color::new(4, 2, 1, 4));
+        let c = premultiplied.from_float(c);
+        assert!(c.r >= 3 && c.r <= 4 && c.g >= 1 && c.g <= 2 && c.b <= 1);
+        assert!(c.a >= 3 && c.a <= 4);
+    }
+
+    #[test]
+    fn simple_from_linear_is_exact_inverse() {
+        // the alpha folding used to be undone after the inverse gamma, which shifted the
+        // brightness of all palette colors, most of all for semi-transparent ones
+        let colorspace = SimpleColorSpace::default();
+        for a in (8..256).step_by(8).chain(Some(255)) {
+            let color = Color::new(200, 100, 50, a as u8);
+            let c = colorspace.from_linear(colorspace.to_float(color));
+            assert!((c.r * 255.0 - 200.0).abs() < 1e-6);
+            assert!((c.g * 255.0 - 100.0).abs() < 1e-6);
+            assert!((c.b * 255.0 - 50.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn simple_round_trip() {
+        check_round_trip(&SimpleColorSpace::default());
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        check_round_trip(&SimpleColorSpace::srgb());
+    }
+
+    #[test]
+    fn oklab_round_trip() {
+        check_round_trip(&OklabColorSpace::default());
+    }
+
+    #[test]
+    fn oklab_folds_alpha_like_simple() {
+        // the factor is derived from the scaled alpha, like in `SimpleColorSpace::to_linear`
+        let oklab = OklabColorSpace::default();
+        let opaque = oklab.to_float(Color::new(200, 100, 50, 255));
+        let transparent = oklab.to_float(Color::new(200, 100, 50, 64));
+        let fold = |a: f64| {
+            a * oklab.scale.a * (1.0 - oklab.transparency_scale) + oklab.transparency_scale
+        };
+        let factor = fold(64.0 / 255.0) / fold(1.0);
+        assert!((transparent.r - opaque.r * factor).abs() < 1e-9);
+        assert!((transparent.g - opaque.g * factor).abs() < 1e-9);
+        assert!((transparent.a - opaque.a * 64.0 / 255.0).abs() < 1e-9);
+        assert!((oklab.from_linear(transparent).r - 200.0 / 255.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn lab_round_trip() {
+        check_round_trip(&LabColorSpace::default());
+        check_round_trip(&LabColorSpace::with_white_point(LabColorSpace::D50));
+    }
+
+    #[test]
+    fn ciede2000_reference_values() {
+        // test data from Sharma, Wu and Dalal, "The CIEDE2000 Color-Difference Formula"
+        let pairs = [((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+                     ((50.0, -1.3802, -84.2814), (50.0, 0.0, -82.7485), 1.0000),
+                     ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+                     ((2.0776, 0.0795, -1.1350), (0.9033, -0.0636, -0.5514), 0.9082)];
+        for &((l1, a1, b1), (l2, a2, b2), expected) in &pairs {
+            let c1 = Colorf {
+                r: l1,
+                g: a1,
+                b: b1,
+                a: 1.0,
+            };
+            let c2 = Colorf {
+                r: l2,
+                g: a2,
+                b: b2,
+                a: 1.0,
+            };
+            assert!((super::ciede2000(c1, c2) - expected).abs() < 1e-4);
+            assert!((super::ciede2000(c2, c1) - expected).abs() < 1e-4);
+        }
+    }
+}
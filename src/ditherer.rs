@@ -1,5 +1,8 @@
 //! Dithered remapping
 
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+
 use super::*;
 
 /// An interface for dithered color remapping.
@@ -15,6 +18,55 @@ pub trait Ditherer {
                  map: &'a ColorMap,
                  colorspace: &'a ColorSpace)
                  -> Box<Iterator<Item = usize> + 'a>;
+
+    /// Remaps a complete image of input pixel (float-)colors to palette indices.
+    ///
+    /// The indices are written to `output` in row-major order without any padding, so it needs
+    /// to hold `image.width() * image.height()` entries, same as the optional per-pixel inputs
+    /// in `options`. Otherwise `Error::SizeMismatch` is returned.
+    ///
+    /// Having random access to the whole image allows ditherers to use multi-row kernels or
+    /// arbitrary scan orders. The default implementation just calls `remap`, ignoring the
+    /// strength, and only replaces the output of kept and skipped pixels afterwards.
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        options.check(image, map, output)?;
+        let indices = self.remap(image.pixels(), image.width(), map, colorspace);
+        for (i, (o, index)) in output.iter_mut().zip(indices).enumerate() {
+            *o = options.fixed_index(i).unwrap_or(index);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of rows above a horizontal band of an image that need to be remapped
+    /// as well for the band to come out (nearly) the same as when remapping the whole image, or
+    /// `None` if the ditherer can't split images into bands.
+    ///
+    /// This is used to remap bands of an image in parallel.
+    fn band_context(&self) -> Option<usize> {
+        Option::None
+    }
+
+    /// Remaps a horizontal band of an image, like `remap_image`.
+    ///
+    /// `first_row` is the row of the full image the band starts at. The default implementation
+    /// ignores it and just calls `remap_image`.
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        let _ = first_row;
+        self.remap_image(image, options, map, colorspace, output)
+    }
 }
 
 /// A Ditherer that simply remaps each pixel to the nearest palette index without any actual
@@ -29,6 +81,179 @@ impl Ditherer for None {
                  -> Box<Iterator<Item = usize> + 'a> {
         Box::new(image.map(move |c| map.find_nearest(c)))
     }
+
+    fn band_context(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+/// How `Ditherer::remap_image` treats a single pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelMode {
+    /// Remap and dither the pixel as usual.
+    Dither,
+    /// Use the given palette index for the pixel.
+    ///
+    /// The pixel passes on its actual quantization error for this index to the pixels that are
+    /// still to be dithered, while its own incoming error is dropped. This is used to keep the
+    /// indices of unchanged pixels between animation frames.
+    Keep(usize),
+    /// Leave the pixel out completely.
+    ///
+    /// No error is taken from or passed on to a skipped pixel, as if it wasn't part of the
+    /// image. Its output index is set to 0. This is used for the padding of a partial last row.
+    Skip,
+}
+
+/// Optional per-pixel inputs of `Ditherer::remap_image`.
+///
+/// Each slice holds one entry per pixel of the image in row-major order.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelOptions<'a> {
+    /// The dithering strength, from 0.0 for no dithering to 1.0 for full dithering (see
+    /// `AdaptiveStrength`). Defaults to full dithering.
+    pub strength: Option<&'a [f64]>,
+    /// The mode of each pixel. Defaults to `PixelMode::Dither`.
+    pub modes: Option<&'a [PixelMode]>,
+}
+
+impl<'a> PixelOptions<'a> {
+    // Checks that the per-pixel inputs and the output match the size of the image and that
+    // all kept indices are in the palette.
+    fn check(&self,
+             image: &ImageView<Colorf>,
+             map: &ColorMap,
+             output: &[usize])
+             -> Result<(), Error> {
+        let len = image.width() * image.height();
+        if output.len() != len || self.strength.map_or(len, |s| s.len()) != len ||
+           self.modes.map_or(len, |m| m.len()) != len {
+            return Err(Error::SizeMismatch);
+        }
+        for &mode in self.modes.unwrap_or(&[]) {
+            if let PixelMode::Keep(index) = mode {
+                if index >= map.num_colors() {
+                    return Err(Error::InvalidIndex(index));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn strength(&self, i: usize) -> f64 {
+        self.strength.map_or(1.0, |s| s[i])
+    }
+
+    fn mode(&self, i: usize) -> PixelMode {
+        self.modes.map_or(PixelMode::Dither, |m| m[i])
+    }
+
+    // Returns the output index of a kept or skipped pixel, or `None` if it is to be dithered.
+    fn fixed_index(&self, i: usize) -> Option<usize> {
+        match self.mode(i) {
+            PixelMode::Dither => Option::None,
+            PixelMode::Keep(index) => Some(index),
+            PixelMode::Skip => Some(0),
+        }
+    }
+
+    // Returns the options for the pixels `start..end`, for example a single row.
+    fn slice(&self, start: usize, end: usize) -> PixelOptions<'a> {
+        PixelOptions {
+            strength: self.strength.map(|s| &s[start..end]),
+            modes: self.modes.map(|m| &m[start..end]),
+        }
+    }
+}
+
+// Scales the alpha channel of a dither offset or error.
+fn scale_alpha(c: Colorf, scale: f64) -> Colorf {
+    Colorf { a: c.a * scale, ..c }
+}
+
+// Moves the alpha value of a color towards the alpha value of its nearest palette color by
+// `1.0 - scale`. Colorspaces like `SimpleColorSpace` fold alpha into the color channels, so
+// without this the dithering of the color channels would still dither the alpha of
+// semi-transparent pixels.
+fn lock_alpha(color: Colorf, scale: f64, map: &ColorMap, colorspace: &ColorSpace) -> Colorf {
+    if scale >= 1.0 {
+        return color;
+    }
+    let nearest = colorspace.from_linear(map.float_color(map.find_nearest(color)));
+    let mut c = colorspace.from_linear(color);
+    c.a += (nearest.a - c.a) * (1.0 - scale);
+    colorspace.to_linear(c)
+}
+
+/// Computes a per-pixel dithering strength map from the local contrast of an image.
+///
+/// Dithering is reduced on hard edges, to keep text and line art crisp, and in completely flat
+/// areas, while it is kept on smooth gradients. The contrast of a pixel is the largest distance
+/// to one of its eight neighbors in quantization colorspace. The strength rises from 0.0 at a
+/// contrast of zero to 1.0 at `flat_threshold`, and falls back to 0.0 between half the
+/// `edge_threshold` and the `edge_threshold`. A threshold of zero disables the respective
+/// reduction.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// # let colorspace = SimpleColorSpace::default();
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 16).unwrap();
+/// let ditherer = ditherer::FloydSteinberg::new();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let adaptive = ditherer::AdaptiveStrength::default();
+/// let indexed_image_data = remapper.remap_adaptive(&image.pixels, image.width, &adaptive)
+///   .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct AdaptiveStrength {
+    pub flat_threshold: f64,
+    pub edge_threshold: f64,
+}
+
+impl Default for AdaptiveStrength {
+    fn default() -> AdaptiveStrength {
+        AdaptiveStrength {
+            flat_threshold: 0.004,
+            edge_threshold: 0.2,
+        }
+    }
+}
+
+impl AdaptiveStrength {
+    /// Returns the dithering strength of each pixel of an image in quantization colorspace.
+    ///
+    /// Returns `Error::ZeroWidth` for a width of zero and `Error::SizeMismatch` if the image
+    /// length is not a multiple of `width`.
+    pub fn strength_map(&self, image: &[Colorf], width: usize) -> Result<Vec<f64>, Error> {
+        let height = ImageView::from_slice(image, width)?.height();
+        Ok((0..image.len())
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let mut contrast = 0f64;
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        if let Some(&c) = image.get(nx + ny * width) {
+                            contrast = contrast.max((c - image[i]).abs());
+                        }
+                    }
+                }
+                let flat = if self.flat_threshold > 0.0 {
+                    (contrast / self.flat_threshold).min(1.0)
+                } else {
+                    1.0
+                };
+                let edge = if self.edge_threshold > 0.0 {
+                    ((self.edge_threshold - contrast) / (self.edge_threshold * 0.5)).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                };
+                flat * edge
+            })
+            .collect())
+    }
 }
 
 /// A 2x2 ordered dithering.
@@ -37,47 +262,1020 @@ impl Ditherer for None {
 /// but might look more pleasing as it appears less like random noise. An ordered dithered image
 /// also has the advantage of compressing a lot better than floyd-steinberg dithered ones.
 ///
+/// See `OrderedMatrix` for larger Bayer matrices and custom threshold matrices.
+///
 /// Note: don't use ordered dithering on images that are intended to be down-scaled later or risk
 /// moire artifacts.
 pub struct Ordered;
-const DITHER_MATRIX: [f64; 4] = [-0.375, 0.125, 0.375, -0.125];
+
+impl Ordered {
+    /// Returns the `OrderedMatrix::default()` equivalent of this ditherer with the dithering of
+    /// the alpha channel scaled by `scale`.
+    ///
+    /// See `OrderedMatrix::alpha_scale`.
+    pub fn alpha_scale(self, scale: f64) -> OrderedMatrix {
+        OrderedMatrix::default().alpha_scale(scale)
+    }
+}
+
 impl Ditherer for Ordered {
     fn remap<'a>(&'a self,
                  image: Box<Iterator<Item = Colorf> + 'a>,
                  width: usize,
                  map: &'a ColorMap,
-                 _: &'a ColorSpace)
+                 colorspace: &'a ColorSpace)
+                 -> Box<Iterator<Item = usize> + 'a> {
+        let matrix = OrderedMatrix::default();
+        Box::new(image.enumerate().map(move |(i, color)| {
+            matrix.remap_pixel(i % width, i / width, color, 1.0, map, colorspace)
+        }))
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        OrderedMatrix::default().remap_image(image, options, map, colorspace, output)
+    }
+
+    fn band_context(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        OrderedMatrix::default().remap_band(image, first_row, options, map, colorspace, output)
+    }
+}
+
+/// An ordered dithering using a Bayer or custom threshold matrix.
+///
+/// This works like `Ordered`, but larger Bayer matrices give a finer texture with more distinct
+/// levels, which helps a lot at low color counts. The default is the 2x2 matrix of `Ordered`.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// # let colorspace = SimpleColorSpace::default();
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 16).unwrap();
+/// let ditherer = ditherer::OrderedMatrix::bayer(8).unwrap();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct OrderedMatrix {
+    matrix: Vec<f64>,
+    width: usize,
+    height: usize,
+    alpha_scale: f64,
+}
+
+impl Default for OrderedMatrix {
+    fn default() -> OrderedMatrix {
+        OrderedMatrix::from_thresholds(DITHER_MATRIX.iter().map(|&t| t + 0.5).collect(), 2)
+    }
+}
+
+const DITHER_MATRIX: [f64; 4] = [-0.375, 0.125, 0.375, -0.125];
+
+impl OrderedMatrix {
+    /// Returns an ordered ditherer using a generated `size`x`size` Bayer matrix.
+    ///
+    /// `size` needs to be a power of two, typical values are 2, 4, 8 and 16. Other sizes return
+    /// `Error::InvalidSize`.
+    pub fn bayer(size: usize) -> Result<OrderedMatrix, Error> {
+        if !size.is_power_of_two() {
+            return Err(Error::InvalidSize);
+        }
+        let ranks = bayer_ranks(size);
+        let count = (size * size) as f64;
+        let thresholds = ranks.iter().map(|&r| (r as f64 + 0.5) / count).collect();
+        Ok(OrderedMatrix::from_thresholds(thresholds, size))
+    }
+
+    /// Returns an ordered ditherer using a custom threshold matrix.
+    ///
+    /// The matrix is given in row-major order with `width` entries per row and is tiled across
+    /// the image. Thresholds are expected in the range 0.0 to 1.0. Returns
+    /// `Error::InvalidSize` if the matrix is empty or not made up of whole rows.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// // a 4x1 line pattern
+    /// let ditherer = ditherer::OrderedMatrix::with_thresholds(&[0.125, 0.625, 0.375, 0.875], 4)
+    ///   .unwrap();
+    /// ```
+    pub fn with_thresholds(thresholds: &[f64], width: usize) -> Result<OrderedMatrix, Error> {
+        let height = thresholds.len() / width.max(1);
+        if width == 0 || height == 0 || width * height != thresholds.len() {
+            return Err(Error::InvalidSize);
+        }
+        Ok(OrderedMatrix::from_thresholds(thresholds.to_vec(), width))
+    }
+
+    // Creates a ditherer from a threshold matrix that is known to be made up of whole rows.
+    fn from_thresholds(thresholds: Vec<f64>, width: usize) -> OrderedMatrix {
+        OrderedMatrix {
+            height: thresholds.len() / width,
+            matrix: thresholds.iter().map(|&t| t - 0.5).collect(),
+            width: width,
+            alpha_scale: 1.0,
+        }
+    }
+
+    /// Returns the ditherer with the dithering of the alpha channel scaled by `scale`.
+    ///
+    /// A scale of 0.0 disables alpha dithering completely, which avoids speckled alpha edges
+    /// when compositing over varying backgrounds. The alpha value of each pixel is then taken
+    /// from its nearest palette color without dithering and the threshold never picks a
+    /// palette color of a different alpha value, so the color channels don't dither the alpha
+    /// either in colorspaces folding alpha into them.
+    pub fn alpha_scale(mut self, scale: f64) -> OrderedMatrix {
+        self.alpha_scale = scale;
+        self
+    }
+}
+
+impl OrderedMatrix {
+    fn remap_pixel(&self,
+                   x: usize,
+                   y: usize,
+                   color: Colorf,
+                   strength: f64,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace)
+                   -> usize {
+        let color = lock_alpha(color, self.alpha_scale, map, colorspace);
+        let dither = self.matrix[(x % self.width) + (y % self.height) * self.width] * strength;
+        let i = map.find_nearest(color);
+        let d = map.neighbor_distance(i);
+        let offset = d * dither * 0.75;
+        let color = color +
+                    Colorf {
+            r: offset,
+            g: offset,
+            b: offset,
+            a: offset * self.alpha_scale,
+        };
+        let dithered = map.find_nearest(color);
+        // the threshold offset of the color channels can still pick a palette color of a
+        // different alpha value where alpha is folded into them
+        if self.alpha_scale <= 0.0 && map.float_color(dithered).a != map.float_color(i).a {
+            i
+        } else {
+            dithered
+        }
+    }
+}
+
+impl Ditherer for OrderedMatrix {
+    fn remap<'a>(&'a self,
+                 image: Box<Iterator<Item = Colorf> + 'a>,
+                 width: usize,
+                 map: &'a ColorMap,
+                 colorspace: &'a ColorSpace)
                  -> Box<Iterator<Item = usize> + 'a> {
-        Box::new(image.enumerate()
-            .map(move |(i, color)| {
-                let x = i % width;
-                let y = i / width;
-                let dither = DITHER_MATRIX[(x & 1) + (y & 1) * 2];
-                let i = map.find_nearest(color);
-                let d = map.neighbor_distance(i);
-                let color = color + (d * dither * 0.75);
-                map.find_nearest(color)
-            }))
+        Box::new(image.enumerate().map(move |(i, color)| {
+            self.remap_pixel(i % width, i / width, color, 1.0, map, colorspace)
+        }))
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        self.remap_band(image, 0, options, map, colorspace, output)
+    }
+
+    fn band_context(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        options.check(image, map, output)?;
+        let width = image.width();
+        for y in 0..image.height() {
+            for (x, &color) in image.row(y).iter().enumerate() {
+                let i = x + y * width;
+                if let Some(index) = options.fixed_index(i) {
+                    output[i] = index;
+                    continue;
+                }
+                let strength = options.strength(i);
+                output[i] = self.remap_pixel(x, first_row + y, color, strength, map, colorspace);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the ranks of a `size`x`size` Bayer matrix in row-major order. `size` needs to be a
+// power of two.
+fn bayer_ranks(size: usize) -> Vec<usize> {
+    let mut ranks = vec![0usize];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0; n * n * 4];
+        for y in 0..n * 2 {
+            for x in 0..n * 2 {
+                let offset = [0, 2, 3, 1][(x / n) + (y / n) * 2];
+                next[x + y * n * 2] = ranks[(x % n) + (y % n) * n] * 4 + offset;
+            }
+        }
+        ranks = next;
+        n *= 2;
+    }
+    ranks
+}
+
+/// A threshold dithering using a tileable blue noise texture.
+///
+/// Blue noise dithering gets much of the visual quality of error diffusion, while each pixel is
+/// still remapped independently of its neighbors like with the `Ordered` ditherer. This makes the
+/// result stable under animation and avoids the regular pattern of Bayer matrices.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// # let colorspace = SimpleColorSpace::default();
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 16).unwrap();
+/// let ditherer = ditherer::BlueNoise::new();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+pub struct BlueNoise {
+    size: usize,
+    alpha_scale: f64,
+    // generated on first use, unless the texture is given by the caller
+    matrix: Mutex<Option<Arc<OrderedMatrix>>>,
+}
+
+impl Default for BlueNoise {
+    fn default() -> BlueNoise {
+        BlueNoise::new()
+    }
+}
+
+impl BlueNoise {
+    /// Returns a blue noise ditherer with a generated 64x64 texture.
+    ///
+    /// The texture is generated on first use and kept by the ditherer, so constructing one is
+    /// cheap and remapping several images with the same ditherer generates it only once.
+    pub fn new() -> BlueNoise {
+        BlueNoise::from_matrix(64, Option::None)
+    }
+
+    /// Returns a blue noise ditherer with a generated `size`x`size` texture.
+    ///
+    /// The texture is generated using the void-and-cluster algorithm, which takes time
+    /// quadratic in the number of texels, so very large sizes are slow to generate. Sizes
+    /// below 2 return `Error::InvalidSize`.
+    pub fn with_size(size: usize) -> Result<BlueNoise, Error> {
+        if size < 2 {
+            return Err(Error::InvalidSize);
+        }
+        Ok(BlueNoise::from_matrix(size, Option::None))
+    }
+
+    /// Returns a blue noise ditherer using a caller supplied texture.
+    ///
+    /// The texture is given in row-major order with `width` entries per row and values in the
+    /// range 0.0 to 1.0, for example loaded from a precomputed blue noise image. Returns
+    /// `Error::InvalidSize` if the texture is empty or not made up of whole rows.
+    pub fn with_texture(texture: &[f64], width: usize) -> Result<BlueNoise, Error> {
+        let matrix = OrderedMatrix::with_thresholds(texture, width)?;
+        Ok(BlueNoise::from_matrix(width, Some(matrix)))
+    }
+
+    /// Returns the ditherer with the dithering of the alpha channel scaled by `scale`.
+    ///
+    /// See `OrderedMatrix::alpha_scale`.
+    pub fn alpha_scale(mut self, scale: f64) -> BlueNoise {
+        self.alpha_scale = scale;
+        let matrix = self.matrix.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(m) = matrix.take() {
+            let m = Arc::try_unwrap(m).unwrap_or_else(|m| (*m).clone());
+            *matrix = Some(Arc::new(m.alpha_scale(scale)));
+        }
+        self
+    }
+
+    fn from_matrix(size: usize, matrix: Option<OrderedMatrix>) -> BlueNoise {
+        BlueNoise {
+            size: size,
+            alpha_scale: 1.0,
+            matrix: Mutex::new(matrix.map(Arc::new)),
+        }
+    }
+
+    // Returns the threshold matrix, generating the texture on first use.
+    fn matrix(&self) -> Arc<OrderedMatrix> {
+        let mut matrix = self.matrix.lock().unwrap_or_else(|e| e.into_inner());
+        matrix.get_or_insert_with(|| {
+                let thresholds = blue_noise_thresholds(self.size);
+                Arc::new(OrderedMatrix::from_thresholds(thresholds, self.size)
+                    .alpha_scale(self.alpha_scale))
+            })
+            .clone()
+    }
+}
+
+impl Ditherer for BlueNoise {
+    fn remap<'a>(&'a self,
+                 image: Box<Iterator<Item = Colorf> + 'a>,
+                 width: usize,
+                 map: &'a ColorMap,
+                 colorspace: &'a ColorSpace)
+                 -> Box<Iterator<Item = usize> + 'a> {
+        let matrix = self.matrix();
+        Box::new(image.enumerate().map(move |(i, color)| {
+            matrix.remap_pixel(i % width, i / width, color, 1.0, map, colorspace)
+        }))
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        self.matrix().remap_image(image, options, map, colorspace, output)
+    }
+
+    fn band_context(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        self.matrix().remap_band(image, first_row, options, map, colorspace, output)
+    }
+}
+
+// Returns the thresholds of a generated `size`x`size` blue noise texture.
+fn blue_noise_thresholds(size: usize) -> Vec<f64> {
+    let count = (size * size) as f64;
+    void_and_cluster(size).iter().map(|&r| (r as f64 + 0.5) / count).collect()
+}
+
+// Generates a tileable `size`x`size` blue noise rank matrix using Ulichney's void-and-cluster
+// method.
+fn void_and_cluster(size: usize) -> Vec<usize> {
+    let n = size * size;
+    let sigma = 1.5f64;
+    let mut kernel = vec![0f64; n];
+    for y in 0..size {
+        for x in 0..size {
+            let dx = x.min(size - x) as f64;
+            let dy = y.min(size - y) as f64;
+            kernel[x + y * size] = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+    let toggle = |energy: &mut [f64], p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for y in 0..size {
+            for x in 0..size {
+                let k = kernel[(x + size - px) % size + ((y + size - py) % size) * size];
+                energy[x + y * size] += k * sign;
+            }
+        }
+    };
+    let find = |pattern: &[bool], energy: &[f64], value: bool, tightest: bool| {
+        let mut best = n;
+        for i in 0..n {
+            if pattern[i] == value &&
+               (best == n || (tightest && energy[i] > energy[best]) ||
+                (!tightest && energy[i] < energy[best])) {
+                best = i;
+            }
+        }
+        best
+    };
+
+    // initial binary pattern: random points, relaxed until the tightest cluster is also the
+    // largest void
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0f64; n];
+    let ones = (n / 10).max(1);
+    let mut seed = 0x2545f491u32;
+    let mut placed = 0;
+    while placed < ones {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let p = seed as usize % n;
+        if !pattern[p] {
+            pattern[p] = true;
+            toggle(&mut energy, p, 1.0);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = find(&pattern, &energy, true, true);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        let void = find(&pattern, &energy, false, false);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    let (prototype, prototype_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..ones).rev() {
+        let cluster = find(&pattern, &energy, true, true);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    pattern = prototype;
+    energy = prototype_energy;
+    for rank in ones..n {
+        let void = find(&pattern, &energy, false, false);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks
+}
+
+/// Thomas Knoll's pattern dithering.
+///
+/// For each pixel a mix of palette colors approximating the input color is determined, which
+/// is then sorted by luminance and indexed by the rank from a Bayer matrix. Unlike `Ordered`,
+/// which only nudges towards a single neighbor color, this handles irregular fixed palettes
+/// (like hardware palettes) well. The palette candidates for a pixel are taken from the nearest
+/// color and its `ColorMap::neighbors`.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let colorspace = SimpleColorSpace::default();
+/// let palette: Vec<Color> = (0..16)
+///     .map(|i| Color::new((i & 1) * 255, (i >> 1 & 1) * 170, (i >> 2 & 1) * 255, 255))
+///     .collect();
+/// let ditherer = ditherer::Pattern::new();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+pub struct Pattern {
+    ranks: Vec<usize>,
+    size: usize,
+    error_multiplier: f64,
+    alpha_scale: f64,
+}
+
+impl Default for Pattern {
+    fn default() -> Pattern {
+        Pattern::new()
+    }
+}
+
+impl Pattern {
+    /// Returns a pattern ditherer using an 8x8 matrix.
+    pub fn new() -> Pattern {
+        Pattern::from_ranks(bayer_ranks(8), 8)
+    }
+
+    /// Returns a pattern ditherer using a `size`x`size` Bayer matrix.
+    ///
+    /// A mix of `size * size` candidates is computed per pixel, so the cost rises quickly with
+    /// the size. `size` needs to be a power of two, other sizes return `Error::InvalidSize`.
+    pub fn with_size(size: usize) -> Result<Pattern, Error> {
+        if !size.is_power_of_two() {
+            return Err(Error::InvalidSize);
+        }
+        Ok(Pattern::from_ranks(bayer_ranks(size), size))
+    }
+
+    fn from_ranks(ranks: Vec<usize>, size: usize) -> Pattern {
+        Pattern {
+            ranks: ranks,
+            size: size,
+            error_multiplier: 0.5,
+            alpha_scale: 1.0,
+        }
+    }
+
+    /// Returns the ditherer with the mixing of the alpha channel scaled by `scale`.
+    ///
+    /// A scale of 0.0 mixes only colors of the nearest alpha value, see
+    /// `OrderedMatrix::alpha_scale`.
+    pub fn alpha_scale(mut self, scale: f64) -> Pattern {
+        self.alpha_scale = scale;
+        self
+    }
+
+    // Computes the mix of `self.ranks.len()` palette candidates for a color in dither space
+    // into `scratch.candidates`, sorted by luminance. The candidates index `scratch.pool`.
+    fn mix(&self, color: Colorf, strength: f64, scratch: &mut PatternScratch) {
+        let pool_colors = &scratch.pool_colors;
+        let mut error = Colorf::zero();
+        scratch.candidates.clear();
+        for _ in 0..self.ranks.len() {
+            let attempt = color +
+                          scale_alpha(error, self.alpha_scale) * (self.error_multiplier * strength);
+            let mut best = 0;
+            let mut best_distance = f64::MAX;
+            for (i, &c) in pool_colors.iter().enumerate() {
+                let distance = (attempt - c).abs();
+                if distance < best_distance {
+                    best = i;
+                    best_distance = distance;
+                }
+            }
+            error += color - pool_colors[best];
+            scratch.candidates.push(best);
+        }
+        let luma = |c: Colorf| c.r * 0.299 + c.g * 0.587 + c.b * 0.114;
+        scratch.candidates.sort_by(|&a, &b| {
+            luma(pool_colors[a]).partial_cmp(&luma(pool_colors[b])).unwrap_or(Ordering::Equal)
+        });
+    }
+
+    fn rank(&self, x: usize, y: usize) -> usize {
+        self.ranks[(x % self.size) + (y % self.size) * self.size]
+    }
+
+    fn remap_pixel(&self,
+                   color: Colorf,
+                   strength: f64,
+                   rank: usize,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   scratch: &mut PatternScratch)
+                   -> usize {
+        let color = lock_alpha(color, self.alpha_scale, map, colorspace);
+        let nearest = map.find_nearest(color);
+        scratch.pool.clear();
+        scratch.pool.push(nearest);
+        scratch.pool.extend_from_slice(map.neighbors(nearest));
+        scratch.pool_colors.clear();
+        scratch.pool_colors
+            .extend(scratch.pool.iter().map(|&i| colorspace.to_dither(map.float_color(i))));
+        self.mix(colorspace.to_dither(color), strength, scratch);
+        scratch.pool[scratch.candidates[rank]]
+    }
+}
+
+// Buffers reused between the pixels of an image, so the pattern ditherer doesn't allocate for
+// every pixel.
+#[derive(Default)]
+struct PatternScratch {
+    pool: Vec<usize>,
+    pool_colors: Vec<Colorf>,
+    candidates: Vec<usize>,
+}
+
+impl Ditherer for Pattern {
+    fn remap<'a>(&'a self,
+                 image: Box<Iterator<Item = Colorf> + 'a>,
+                 width: usize,
+                 map: &'a ColorMap,
+                 colorspace: &'a ColorSpace)
+                 -> Box<Iterator<Item = usize> + 'a> {
+        let mut scratch = PatternScratch::default();
+        Box::new(image.enumerate().map(move |(i, color)| {
+            let rank = self.rank(i % width, i / width);
+            self.remap_pixel(color, 1.0, rank, map, colorspace, &mut scratch)
+        }))
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        self.remap_band(image, 0, options, map, colorspace, output)
+    }
+
+    fn band_context(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        options.check(image, map, output)?;
+        let width = image.width();
+        let mut scratch = PatternScratch::default();
+        for y in 0..image.height() {
+            for (x, &color) in image.row(y).iter().enumerate() {
+                let i = x + y * width;
+                if let Some(index) = options.fixed_index(i) {
+                    output[i] = index;
+                    continue;
+                }
+                let (rank, strength) = (self.rank(x, first_row + y), options.strength(i));
+                output[i] = self.remap_pixel(color, strength, rank, map, colorspace, &mut scratch);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A generic error diffusion ditherer.
+///
+/// The quantization error of each pixel is distributed to not yet processed neighboring pixels
+/// according to a kernel, which can span any number of rows. Constructors for the classic
+/// kernels are provided, custom ones can be passed to `ErrorDiffusion::new`.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// # let colorspace = SimpleColorSpace::default();
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 16).unwrap();
+/// let ditherer = ditherer::ErrorDiffusion::atkinson();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+pub struct ErrorDiffusion {
+    kernel: Vec<(isize, usize, f64)>,
+    rows: usize,
+    damping: f64,
+    serpentine: bool,
+    alpha_scale: f64,
+}
+
+impl ErrorDiffusion {
+    /// Returns an error diffusion ditherer with a custom kernel.
+    ///
+    /// The kernel is a list of `(dx, dy, weight)` entries relative to the current pixel. Only
+    /// pixels after the current one may receive errors, so `dy` needs to be positive or `dx`
+    /// needs to be positive for `dy == 0`, otherwise `Error::InvalidKernel` is returned. The
+    /// error a pixel receives is multiplied by `damping` before it is passed on, a value below
+    /// 1.0 reduces color bleeding.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// // the "false" Floyd-Steinberg kernel
+    /// let ditherer = ditherer::ErrorDiffusion::new(&[(1, 0, 3.0 / 8.0),
+    ///                                                (0, 1, 3.0 / 8.0),
+    ///                                                (1, 1, 2.0 / 8.0)],
+    ///                                              1.0)
+    ///   .unwrap();
+    /// assert!(ditherer::ErrorDiffusion::new(&[(-1, 0, 1.0)], 1.0).is_err());
+    /// ```
+    pub fn new(kernel: &[(isize, usize, f64)], damping: f64) -> Result<ErrorDiffusion, Error> {
+        if !kernel.iter().all(|&(dx, dy, _)| dy > 0 || dx > 0) {
+            return Err(Error::InvalidKernel);
+        }
+        Ok(Self::from_kernel(kernel, damping))
+    }
+
+    // Creates a ditherer from a kernel that is known to be valid.
+    fn from_kernel(kernel: &[(isize, usize, f64)], damping: f64) -> ErrorDiffusion {
+        ErrorDiffusion {
+            kernel: kernel.to_vec(),
+            rows: kernel.iter().map(|&(_, dy, _)| dy + 1).max().unwrap_or(1),
+            damping: damping,
+            serpentine: false,
+            alpha_scale: 1.0,
+        }
+    }
+
+    /// Returns the ditherer with serpentine scanning enabled.
+    ///
+    /// Every other row is processed right-to-left with a mirrored kernel, which avoids the
+    /// diagonal "worm" artifacts a fixed scan direction produces on smooth gradients.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// let ditherer = ditherer::ErrorDiffusion::sierra().serpentine();
+    /// ```
+    pub fn serpentine(mut self) -> ErrorDiffusion {
+        self.serpentine = true;
+        self
+    }
+
+    /// Returns the ditherer with the error diffusion of the alpha channel scaled by `scale`.
+    ///
+    /// A scale of 0.0 disables alpha dithering completely, which avoids speckled alpha edges
+    /// when compositing over varying backgrounds. The alpha value of each pixel is then taken
+    /// from its nearest palette color without dithering, so in colorspaces like
+    /// `SimpleColorSpace`, which fold alpha into the color channels, only the color error is
+    /// diffused.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// let ditherer = ditherer::ErrorDiffusion::floyd_steinberg().alpha_scale(0.0);
+    /// ```
+    pub fn alpha_scale(mut self, scale: f64) -> ErrorDiffusion {
+        self.alpha_scale = scale;
+        self
+    }
+
+    fn with_divisor(kernel: &[(isize, usize, f64)], divisor: f64) -> ErrorDiffusion {
+        let kernel: Vec<_> = kernel.iter().map(|&(dx, dy, w)| (dx, dy, w / divisor)).collect();
+        Self::from_kernel(&kernel, 1.0)
+    }
+
+    /// Returns a ditherer using the vanilla Floyd-Steinberg kernel.
+    pub fn floyd_steinberg() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0)
+    }
+
+    /// Returns a ditherer using the Jarvis, Judice and Ninke kernel.
+    pub fn jarvis_judice_ninke() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 7.0), (2, 0, 5.0),
+                             (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+                             (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0)],
+                           48.0)
+    }
+
+    /// Returns a ditherer using the Stucki kernel.
+    pub fn stucki() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 8.0), (2, 0, 4.0),
+                             (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0),
+                             (-2, 2, 1.0), (-1, 2, 2.0), (0, 2, 4.0), (1, 2, 2.0), (2, 2, 1.0)],
+                           42.0)
+    }
+
+    /// Returns a ditherer using the Burkes kernel.
+    pub fn burkes() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 8.0), (2, 0, 4.0),
+                             (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0)],
+                           32.0)
+    }
+
+    /// Returns a ditherer using the three row Sierra kernel.
+    pub fn sierra() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 5.0), (2, 0, 3.0),
+                             (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+                             (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0)],
+                           32.0)
+    }
+
+    /// Returns a ditherer using the two row Sierra kernel.
+    pub fn sierra_two_row() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 4.0), (2, 0, 3.0),
+                             (-2, 1, 1.0), (-1, 1, 2.0), (0, 1, 3.0), (1, 1, 2.0), (2, 1, 1.0)],
+                           16.0)
+    }
+
+    /// Returns a ditherer using the Sierra Lite kernel.
+    pub fn sierra_lite() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 2.0), (-1, 1, 1.0), (0, 1, 1.0)], 4.0)
+    }
+
+    /// Returns a ditherer using Bill Atkinson's kernel.
+    ///
+    /// Only 3/4 of the error is propagated, which results in more contrast and less noise in
+    /// flat areas at the cost of detail in very light and dark areas.
+    pub fn atkinson() -> ErrorDiffusion {
+        Self::with_divisor(&[(1, 0, 1.0), (2, 0, 1.0),
+                             (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0),
+                             (0, 2, 1.0)],
+                           8.0)
+    }
+
+    // Remaps the next row of pixels, adding the incoming errors from the ring buffer in
+    // `errors` (scaled by the optional strength of the row) and distributing the new errors
+    // into it. Skipped pixels neither take nor pass on any error.
+    fn remap_row(&self,
+                 errors: &mut ErrorRows,
+                 row: &[Colorf],
+                 options: PixelOptions,
+                 map: &ColorMap,
+                 colorspace: &ColorSpace,
+                 output: &mut [usize]) {
+        let (y, width) = (errors.y, errors.width);
+        let current = (y % self.rows) * width;
+        let reverse = self.serpentine && (y & 1) == 1;
+        for i in 0..row.len() {
+            let x = if reverse { row.len() - 1 - i } else { i };
+            let (index, error) = match options.mode(x) {
+                PixelMode::Dither => {
+                    let color = lock_alpha(row[x], self.alpha_scale, map, colorspace);
+                    let c = colorspace.to_dither(color);
+                    let incoming = scale_alpha(errors.data[current + x], self.alpha_scale) *
+                                   options.strength(x);
+                    let index = map.find_nearest(colorspace.from_dither(c + incoming));
+                    (index, c + incoming * self.damping)
+                }
+                PixelMode::Keep(index) => (index, colorspace.to_dither(row[x])),
+                PixelMode::Skip => {
+                    output[x] = 0;
+                    continue;
+                }
+            };
+            let error = error - colorspace.to_dither(map.float_color(index));
+            for &(dx, dy, weight) in &self.kernel {
+                let tx = if reverse { x as isize - dx } else { x as isize + dx };
+                if tx >= 0 && (tx as usize) < width {
+                    errors.data[((y + dy) % self.rows) * width + tx as usize] += error * weight;
+                }
+            }
+            output[x] = index;
+        }
+        for error in &mut errors.data[current..current + width] {
+            *error = Colorf::zero();
+        }
+        errors.y += 1;
+    }
+}
+
+// The pending errors of the next `rows` rows of an image `width` pixels wide, starting at row
+// `y`.
+struct ErrorRows {
+    data: Vec<Colorf>,
+    width: usize,
+    y: usize,
+}
+
+impl ErrorRows {
+    fn new(width: usize, rows: usize, y: usize) -> ErrorRows {
+        ErrorRows {
+            data: (0..width * rows).map(|_| Colorf::zero()).collect(),
+            width: width,
+            y: y,
+        }
+    }
+}
+
+struct ErrorDiffusionIter<'a> {
+    ditherer: &'a ErrorDiffusion,
+    image: Box<Iterator<Item = Colorf> + 'a>,
+    width: usize,
+    map: &'a ColorMap,
+    colorspace: &'a ColorSpace,
+    errors: ErrorRows,
+    row: Vec<Colorf>,
+    output: Vec<usize>,
+    pos: usize,
+}
+
+impl<'a> Iterator for ErrorDiffusionIter<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        if self.pos == self.output.len() {
+            self.row.clear();
+            self.row.extend(self.image.by_ref().take(self.width));
+            self.output.clear();
+            self.output.resize(self.row.len(), 0);
+            self.pos = 0;
+            self.ditherer.remap_row(&mut self.errors,
+                                    &self.row,
+                                    PixelOptions::default(),
+                                    self.map,
+                                    self.colorspace,
+                                    &mut self.output);
+        }
+        let index = self.output.get(self.pos).cloned();
+        self.pos += 1;
+        index
+    }
+}
+
+impl Ditherer for ErrorDiffusion {
+    fn remap<'a>(&'a self,
+                 image: Box<Iterator<Item = Colorf> + 'a>,
+                 width: usize,
+                 map: &'a ColorMap,
+                 colorspace: &'a ColorSpace)
+                 -> Box<Iterator<Item = usize> + 'a> {
+        Box::new(ErrorDiffusionIter {
+            ditherer: self,
+            image: image,
+            width: width,
+            map: map,
+            colorspace: colorspace,
+            errors: ErrorRows::new(width, self.rows, 0),
+            row: Vec::with_capacity(width),
+            output: Vec::with_capacity(width),
+            pos: 0,
+        })
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        self.remap_band(image, 0, options, map, colorspace, output)
+    }
+
+    fn band_context(&self) -> Option<usize> {
+        // the error buffer settles after a few rows, so bands start out with some rows of
+        // context above them to hide the seams
+        Some(self.rows * 8)
+    }
+
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        options.check(image, map, output)?;
+        let width = image.width();
+        let mut errors = ErrorRows::new(width, self.rows, first_row);
+        for (y, output) in output.chunks_mut(width).enumerate() {
+            self.remap_row(&mut errors,
+                           image.row(y),
+                           options.slice(y * width, (y + 1) * width),
+                           map,
+                           colorspace,
+                           output);
+        }
+        Ok(())
     }
 }
 
 /// A few variants of a Floyd-Steinberg ditherer.
-pub struct FloydSteinberg(f64, f64, f64, f64, f64);
+///
+/// These are `ErrorDiffusion` ditherers with the Floyd-Steinberg kernel and some tweaks.
+pub struct FloydSteinberg(ErrorDiffusion);
 impl FloydSteinberg {
     /// Returns a floyd-steinberg variant that reduces color bleeding.
     pub fn new() -> FloydSteinberg {
-        FloydSteinberg(7.0 / 16.0, 3.0 / 16.0, 5.0 / 16.0, 1.0 / 16.0, 0.8)
+        FloydSteinberg::with_weights(7.0, 3.0, 5.0, 1.0, 0.8)
     }
     /// Returns a vanilla floyd-steinberg ditherer as originally described.
     pub fn vanilla() -> FloydSteinberg {
-        FloydSteinberg(7.0 / 16.0, 3.0 / 16.0, 5.0 / 16.0, 1.0 / 16.0, 1.0)
+        FloydSteinberg::with_weights(7.0, 3.0, 5.0, 1.0, 1.0)
     }
     /// Returns a modified floyd-steinber ditherer slightly favoring checker board patterns.
     ///
     /// The resulting dithering looks a little less like random noise. Don't use for images
     /// that are later down-scaled, as that will risk moire artifacts.
     pub fn checkered() -> FloydSteinberg {
-        FloydSteinberg(7.0 / 16.0, 1.5 / 16.0, 6.5 / 16.0, 1.0 / 16.0, 0.5)
+        FloydSteinberg::with_weights(7.0, 1.5, 6.5, 1.0, 0.5)
+    }
+    /// Returns the ditherer with serpentine scanning enabled.
+    ///
+    /// See `ErrorDiffusion::serpentine`.
+    pub fn serpentine(self) -> FloydSteinberg {
+        FloydSteinberg(self.0.serpentine())
+    }
+    /// Returns the ditherer with the error diffusion of the alpha channel scaled by `scale`.
+    ///
+    /// See `ErrorDiffusion::alpha_scale`.
+    pub fn alpha_scale(self, scale: f64) -> FloydSteinberg {
+        FloydSteinberg(self.0.alpha_scale(scale))
+    }
+
+    fn with_weights(right: f64,
+                    down_left: f64,
+                    down: f64,
+                    down_right: f64,
+                    damping: f64)
+                    -> FloydSteinberg {
+        FloydSteinberg(ErrorDiffusion::from_kernel(&[(1, 0, right / 16.0),
+                                                     (-1, 1, down_left / 16.0),
+                                                     (0, 1, down / 16.0),
+                                                     (1, 1, down_right / 16.0)],
+                                                   damping))
     }
 }
 impl Ditherer for FloydSteinberg {
@@ -87,23 +1285,485 @@ impl Ditherer for FloydSteinberg {
                  map: &'a ColorMap,
                  colorspace: &'a ColorSpace)
                  -> Box<Iterator<Item = usize> + 'a> {
-        let mut errors: Vec<Colorf> = (0..width * 2).map(|_| Colorf::zero()).collect();
-        Box::new(image.enumerate()
-            .map(move |(i, c)| {
-                let x = i % width;
-                let y = i / width;
-                let y = y & 1;
-                let row = y * width;
-                let other = (y ^ 1) * width;
-                let c = colorspace.to_dither(c);
-                let index = map.find_nearest(colorspace.from_dither(c + errors[row + x]));
-                let c2 = map.float_color(index);
-                let error = c + errors[row + x] * self.4 - colorspace.to_dither(c2);
-                errors[row + (x + 1) % width] += error * self.0;
-                errors[other + (x + 1) % width] = error * self.3;
-                errors[other + x] += error * self.2;
-                errors[other + (x + width - 1) % width] += error * self.1;
-                index
-            }))
+        self.0.remap(image, width, map, colorspace)
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        self.0.remap_image(image, options, map, colorspace, output)
+    }
+
+    fn band_context(&self) -> Option<usize> {
+        self.0.band_context()
+    }
+
+    fn remap_band(&self,
+                  image: &ImageView<Colorf>,
+                  first_row: usize,
+                  options: PixelOptions,
+                  map: &ColorMap,
+                  colorspace: &ColorSpace,
+                  output: &mut [usize])
+                  -> Result<(), Error> {
+        self.0.remap_band(image, first_row, options, map, colorspace, output)
+    }
+}
+
+/// Riemersma dithering along a Hilbert curve.
+///
+/// The image is traversed along a (generalized) Hilbert curve and the quantization error is
+/// diffused to the following pixels on the curve using an exponentially decaying history. This
+/// avoids the directional artifacts of row-based error diffusion on large flat gradients.
+///
+/// This ditherer needs random access to the image, so remapping an iterator buffers the whole
+/// image first.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// # let image = testdata::test_image();
+/// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+/// # let colorspace = SimpleColorSpace::default();
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 16).unwrap();
+/// let ditherer = ditherer::Riemersma::new();
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
+/// ```
+pub struct Riemersma {
+    weights: Vec<f64>,
+    alpha_scale: f64,
+}
+
+impl Default for Riemersma {
+    fn default() -> Riemersma {
+        Riemersma::new()
+    }
+}
+
+impl Riemersma {
+    /// Returns a Riemersma ditherer with a history of 16 pixels and a ratio of 1/16 between
+    /// the weights of the oldest and the newest error.
+    pub fn new() -> Riemersma {
+        Riemersma::from_weights(16, 1.0 / 16.0)
+    }
+
+    /// Returns a Riemersma ditherer with a custom history length and weight ratio.
+    ///
+    /// The weights decay exponentially from the most recent error to the oldest one, which
+    /// gets `ratio` times the weight of the most recent. A `length` of 0 returns
+    /// `Error::InvalidSize`.
+    pub fn with_history(length: usize, ratio: f64) -> Result<Riemersma, Error> {
+        if length == 0 {
+            return Err(Error::InvalidSize);
+        }
+        Ok(Riemersma::from_weights(length, ratio))
+    }
+
+    fn from_weights(length: usize, ratio: f64) -> Riemersma {
+        let weights: Vec<f64> = (0..length)
+            .map(|i| ratio.powf(i as f64 / (length - 1).max(1) as f64))
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        Riemersma {
+            weights: weights.iter().map(|w| w / sum).collect(),
+            alpha_scale: 1.0,
+        }
+    }
+
+    /// Returns the ditherer with the error diffusion of the alpha channel scaled by `scale`.
+    ///
+    /// See `ErrorDiffusion::alpha_scale`.
+    pub fn alpha_scale(mut self, scale: f64) -> Riemersma {
+        self.alpha_scale = scale;
+        self
+    }
+}
+
+impl Ditherer for Riemersma {
+    fn remap<'a>(&'a self,
+                 image: Box<Iterator<Item = Colorf> + 'a>,
+                 width: usize,
+                 map: &'a ColorMap,
+                 colorspace: &'a ColorSpace)
+                 -> Box<Iterator<Item = usize> + 'a> {
+        let mut image: Vec<Colorf> = image.collect();
+        let len = image.len();
+        // pad a partial last row with skipped pixels, which are dropped from the result again
+        let padded_len = len + (width - len % width) % width;
+        image.resize(padded_len, Colorf::zero());
+        let mut modes = vec![PixelMode::Dither; len];
+        modes.resize(padded_len, PixelMode::Skip);
+        let options = PixelOptions { modes: Some(&modes), ..PixelOptions::default() };
+        let mut output = vec![0; padded_len];
+        // the padded image and the output always match, so this can't fail
+        let remapped = ImageView::from_slice(&image, width)
+            .and_then(|view| self.remap_image(&view, options, map, colorspace, &mut output));
+        debug_assert!(remapped.is_ok());
+        output.truncate(len);
+        Box::new(output.into_iter())
+    }
+
+    fn remap_image(&self,
+                   image: &ImageView<Colorf>,
+                   options: PixelOptions,
+                   map: &ColorMap,
+                   colorspace: &ColorSpace,
+                   output: &mut [usize])
+                   -> Result<(), Error> {
+        options.check(image, map, output)?;
+        let length = self.weights.len();
+        let mut history: Vec<Colorf> = (0..length).map(|_| Colorf::zero()).collect();
+        let width = image.width();
+        // skipped pixels don't advance the history
+        let mut step = 0;
+        for i in hilbert_curve(width, image.height()) {
+            let color = image.get(i % width, i / width);
+            let (index, c) = match options.mode(i) {
+                PixelMode::Dither => {
+                    let color = lock_alpha(color, self.alpha_scale, map, colorspace);
+                    let mut incoming = Colorf::zero();
+                    for (j, &weight) in self.weights.iter().enumerate() {
+                        incoming += history[(step + length - 1 - j) % length] * weight;
+                    }
+                    let incoming = scale_alpha(incoming, self.alpha_scale);
+                    let c = colorspace.to_dither(color) + incoming * options.strength(i);
+                    (map.find_nearest(colorspace.from_dither(c)), c)
+                }
+                PixelMode::Keep(index) => (index, colorspace.to_dither(color)),
+                PixelMode::Skip => {
+                    output[i] = 0;
+                    continue;
+                }
+            };
+            history[step % length] = c - colorspace.to_dither(map.float_color(index));
+            output[i] = index;
+            step += 1;
+        }
+        Ok(())
+    }
+}
+
+// Returns the pixel indices of a `width`x`height` image in the order of a generalized Hilbert
+// curve, which also covers non-square and non-power-of-two sizes.
+fn hilbert_curve(width: usize, height: usize) -> Vec<usize> {
+    let mut out = Vec::with_capacity(width * height);
+    if width == 0 || height == 0 {
+        return out;
+    }
+    let (w, h) = (width as isize, height as isize);
+    let block = if width >= height {
+        CurveBlock { x: 0, y: 0, ax: w, ay: 0, bx: 0, by: h }
+    } else {
+        CurveBlock { x: 0, y: 0, ax: 0, ay: h, bx: w, by: 0 }
+    };
+    block.generate(width, &mut out);
+    out
+}
+
+// A rectangular part of the image covered by one section of the Hilbert curve, starting at
+// `(x, y)` and spanned by the major axis `(ax, ay)` and the minor axis `(bx, by)`.
+#[derive(Clone, Copy)]
+struct CurveBlock {
+    x: isize,
+    y: isize,
+    ax: isize,
+    ay: isize,
+    bx: isize,
+    by: isize,
+}
+
+impl CurveBlock {
+    // Appends the pixel indices of this block in curve order to `out`.
+    fn generate(self, width: usize, out: &mut Vec<usize>) {
+        let CurveBlock { x, y, ax, ay, bx, by } = self;
+        let w = (ax + ay).abs();
+        let h = (bx + by).abs();
+        let (dax, day) = (ax.signum(), ay.signum());
+        let (dbx, dby) = (bx.signum(), by.signum());
+        if h == 1 || w == 1 {
+            let (dx, dy, n) = if h == 1 { (dax, day, w) } else { (dbx, dby, h) };
+            for i in 0..n {
+                out.push((x + dx * i) as usize + (y + dy * i) as usize * width);
+            }
+            return;
+        }
+        let (mut ax2, mut ay2) = (ax.div_euclid(2), ay.div_euclid(2));
+        let (mut bx2, mut by2) = (bx.div_euclid(2), by.div_euclid(2));
+        let w2 = (ax2 + ay2).abs();
+        let h2 = (bx2 + by2).abs();
+        if 2 * w > 3 * h {
+            if w2 % 2 != 0 && w > 2 {
+                ax2 += dax;
+                ay2 += day;
+            }
+            CurveBlock { ax: ax2, ay: ay2, ..self }.generate(width, out);
+            CurveBlock {
+                    x: x + ax2,
+                    y: y + ay2,
+                    ax: ax - ax2,
+                    ay: ay - ay2,
+                    ..self
+                }
+                .generate(width, out);
+        } else {
+            if h2 % 2 != 0 && h > 2 {
+                bx2 += dbx;
+                by2 += dby;
+            }
+            CurveBlock { x: x, y: y, ax: bx2, ay: by2, bx: ax2, by: ay2 }.generate(width, out);
+            CurveBlock {
+                    x: x + bx2,
+                    y: y + by2,
+                    bx: bx - bx2,
+                    by: by - by2,
+                    ..self
+                }
+                .generate(width, out);
+            CurveBlock {
+                    x: x + (ax - dax) + (bx2 - dbx),
+                    y: y + (ay - day) + (by2 - dby),
+                    ax: -bx2,
+                    ay: -by2,
+                    bx: -(ax - ax2),
+                    by: -(ay - ay2),
+                }
+                .generate(width, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_weights() {
+        for ditherer in &[ErrorDiffusion::floyd_steinberg(),
+                          ErrorDiffusion::jarvis_judice_ninke(),
+                          ErrorDiffusion::stucki(),
+                          ErrorDiffusion::burkes(),
+                          ErrorDiffusion::sierra(),
+                          ErrorDiffusion::sierra_two_row(),
+                          ErrorDiffusion::sierra_lite()] {
+            let sum: f64 = ditherer.kernel.iter().map(|&(_, _, w)| w).sum();
+            assert!((sum - 1.0).abs() < 1e-9);
+        }
+        let sum: f64 = ErrorDiffusion::atkinson().kernel.iter().map(|&(_, _, w)| w).sum();
+        assert!((sum - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bayer_matrices() {
+        assert_eq!(OrderedMatrix::bayer(2).unwrap().matrix, DITHER_MATRIX.to_vec());
+        assert_eq!(OrderedMatrix::default().matrix, DITHER_MATRIX.to_vec());
+        assert_eq!(OrderedMatrix::bayer(6).err(), Some(Error::InvalidSize));
+        assert_eq!(OrderedMatrix::with_thresholds(&[0.5; 5], 2).err(), Some(Error::InvalidSize));
+        for &size in &[4, 8, 16] {
+            let mut matrix = OrderedMatrix::bayer(size).unwrap().matrix;
+            matrix.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let count = (size * size) as f64;
+            for (i, &t) in matrix.iter().enumerate() {
+                assert_eq!(t, (i as f64 + 0.5) / count - 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_texture() {
+        let mut ranks = void_and_cluster(16);
+        // every 4x4 block should cover a good part of the value range
+        for block in 0..16 {
+            let (bx, by) = ((block % 4) * 4, (block / 4) * 4);
+            let sum: usize = (0..16).map(|i| ranks[bx + i % 4 + (by + i / 4) * 16]).sum();
+            assert!(sum > 16 * 64 && sum < 16 * 192);
+        }
+        ranks.sort();
+        assert_eq!(ranks, (0..256).collect::<Vec<_>>());
+
+        assert_eq!(BlueNoise::with_size(1).err(), Some(Error::InvalidSize));
+        let ditherer = BlueNoise::default();
+        assert!(ditherer.matrix.lock().unwrap().is_none());
+        let matrix = ditherer.matrix();
+        assert_eq!(matrix.matrix, BlueNoise::with_size(64).unwrap().matrix().matrix);
+        // the texture is only generated once
+        assert!(Arc::ptr_eq(&matrix, &ditherer.matrix()));
+        assert_eq!(ditherer.alpha_scale(0.5).matrix().alpha_scale, 0.5);
+        assert_eq!(BlueNoise::with_texture(&[], 4).err(), Some(Error::InvalidSize));
+    }
+
+    #[test]
+    fn pattern_mixes_two_colors() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let map = ColorMap::new(&palette, &colorspace);
+        let mid = colorspace.from_dither((colorspace.to_dither(map.float_color(0)) +
+                                          colorspace.to_dither(map.float_color(1))) *
+                                         0.5);
+        let ditherer = Pattern::with_size(4).unwrap();
+        let indices: Vec<usize> =
+            ditherer.remap(Box::new((0..16).map(move |_| mid)), 4, &map, &colorspace).collect();
+        assert_eq!(indices.iter().filter(|&&i| i == 1).count(), 8);
+        assert_eq!(Pattern::with_size(6).err(), Some(Error::InvalidSize));
+    }
+
+    #[test]
+    fn hilbert_curve_covers_image() {
+        for &(width, height) in &[(1, 1), (1, 9), (7, 5), (10, 3), (16, 16), (33, 70)] {
+            let curve = hilbert_curve(width, height);
+            let mut sorted = curve.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..width * height).collect::<Vec<_>>());
+            for pair in curve.windows(2) {
+                let (x0, y0) = ((pair[0] % width) as isize, (pair[0] / width) as isize);
+                let (x1, y1) = ((pair[1] % width) as isize, (pair[1] / width) as isize);
+                assert_eq!((x0 - x1).abs() + (y0 - y1).abs(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn riemersma_history() {
+        assert_eq!(Riemersma::with_history(0, 0.5).err(), Some(Error::InvalidSize));
+        let weights = Riemersma::with_history(1, 0.5).unwrap().weights;
+        assert_eq!(weights, vec![1.0]);
+        assert_eq!(Riemersma::default().weights,
+                   Riemersma::with_history(16, 1.0 / 16.0).unwrap().weights);
+    }
+
+    #[test]
+    fn adaptive_strength() {
+        let colorspace = SimpleColorSpace::default();
+        // a flat area, a gentle gradient and a hard edge
+        let image: Vec<Colorf> = [40u8, 40, 40, 40, 42, 44, 46, 48, 255, 255]
+            .iter()
+            .map(|&v| colorspace.to_float(Color::new(v, v, v, 255)))
+            .collect();
+        let strength = AdaptiveStrength::default().strength_map(&image, 10).unwrap();
+        assert_eq!(strength[1], 0.0);
+        assert_eq!(strength[5], 1.0);
+        assert_eq!(strength[8], 0.0);
+        let adaptive = AdaptiveStrength { edge_threshold: 0.0, ..AdaptiveStrength::default() };
+        let strength = adaptive.strength_map(&image, 10).unwrap();
+        assert_eq!(strength[1], 0.0);
+        assert_eq!(strength[5], 1.0);
+        assert_eq!(strength[8], 1.0);
+
+        let adaptive = AdaptiveStrength::default();
+        assert_eq!(adaptive.strength_map(&image, 0), Err(Error::ZeroWidth));
+        assert_eq!(adaptive.strength_map(&image, 4), Err(Error::SizeMismatch));
+        let map = ColorMap::new(&[Color::new(0, 0, 0, 255)], &colorspace);
+        let view = ImageView::from_slice(&image, 5).unwrap();
+        let options = PixelOptions { strength: Some(&strength[..5]), ..PixelOptions::default() };
+        let mut indices = [0; 10];
+        let ditherer = FloydSteinberg::new();
+        assert_eq!(ditherer.remap_image(&view, options, &map, &colorspace, &mut indices),
+                   Err(Error::SizeMismatch));
+    }
+
+    #[test]
+    fn alpha_scale_disables_alpha_dithering() {
+        let colorspace = SimpleColorSpace::default();
+        for &(r, g, b) in &[(0, 0, 0), (200, 100, 50), (255, 255, 255)] {
+            let palette = [Color::new(r, g, b, 0), Color::new(r, g, b, 255)];
+            let map = ColorMap::new(&palette, &colorspace);
+            let image: Vec<Colorf> =
+                (0..64).map(|_| colorspace.to_float(Color::new(r, g, b, 100))).collect();
+            let view = ImageView::from_slice(&image, 8).unwrap();
+            let ditherers: Vec<Box<Ditherer>> =
+                vec![Box::new(ErrorDiffusion::floyd_steinberg().alpha_scale(0.0)),
+                     Box::new(OrderedMatrix::bayer(4).unwrap().alpha_scale(0.0)),
+                     Box::new(Ordered.alpha_scale(0.0)),
+                     Box::new(BlueNoise::with_size(8).unwrap().alpha_scale(0.0)),
+                     Box::new(Pattern::new().alpha_scale(0.0)),
+                     Box::new(Riemersma::new().alpha_scale(0.0))];
+            for ditherer in &ditherers {
+                let mut indices = vec![0; 64];
+                let options = PixelOptions::default();
+                ditherer.remap_image(&view, options, &map, &colorspace, &mut indices).unwrap();
+                assert!(indices.iter().all(|&i| i == indices[0]));
+            }
+            let mut dithered = vec![0; 64];
+            ErrorDiffusion::floyd_steinberg()
+                .remap_image(&view, PixelOptions::default(), &map, &colorspace, &mut dithered)
+                .unwrap();
+            assert!(dithered.contains(&0) && dithered.contains(&1));
+        }
+    }
+
+    #[test]
+    fn remaps_partial_last_row() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let map = ColorMap::new(&palette, &colorspace);
+        let image: Vec<Colorf> =
+            (0..10).map(|i| colorspace.to_float(Color::new(i * 25, i * 25, i * 25, 255))).collect();
+        let ditherer = ErrorDiffusion::jarvis_judice_ninke();
+        let indices: Vec<usize> = ditherer.remap(Box::new(image.into_iter()), 4, &map, &colorspace)
+            .collect();
+        assert_eq!(indices.len(), 10);
+        assert_eq!(indices[0], 0);
+        assert_eq!(indices[9], 1);
+    }
+
+    #[test]
+    fn skipped_pixels_do_not_pass_on_errors() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let map = ColorMap::new(&palette, &colorspace);
+        let gray = |i: usize| colorspace.to_float(Color::new(60 + i as u8 * 4, 90, 120, 255));
+        let image: Vec<Colorf> = (0..31).map(&gray).collect();
+        let ditherer = Riemersma::new();
+        let indices: Vec<usize> =
+            ditherer.remap(Box::new(image.iter().cloned()), 7, &map, &colorspace).collect();
+        // the padding of the partial last row has no influence on the other pixels, whatever
+        // its color
+        let mut padded = image.clone();
+        padded.resize(35, colorspace.to_float(Color::new(128, 128, 128, 255)));
+        let view = ImageView::from_slice(&padded, 7).unwrap();
+        let modes: Vec<PixelMode> =
+            (0..35).map(|i| if i < 31 { PixelMode::Dither } else { PixelMode::Skip }).collect();
+        let options = PixelOptions { modes: Some(&modes), ..PixelOptions::default() };
+        let mut skipped = vec![0; 35];
+        ditherer.remap_image(&view, options, &map, &colorspace, &mut skipped).unwrap();
+        assert_eq!(skipped[..31], indices[..]);
+        assert_eq!(skipped[31..], [0; 4]);
+        let mut unmasked = vec![0; 35];
+        ditherer.remap_image(&view, PixelOptions::default(), &map, &colorspace, &mut unmasked)
+            .unwrap();
+        assert!(unmasked[..31] != indices[..]);
+
+        let options = PixelOptions { modes: Some(&modes[..34]), ..PixelOptions::default() };
+        assert_eq!(ditherer.remap_image(&view, options, &map, &colorspace, &mut skipped),
+                   Err(Error::SizeMismatch));
+        let modes = vec![PixelMode::Keep(2); 35];
+        let options = PixelOptions { modes: Some(&modes), ..PixelOptions::default() };
+        assert_eq!(ditherer.remap_image(&view, options, &map, &colorspace, &mut skipped),
+                   Err(Error::InvalidIndex(2)));
+        assert_eq!(ErrorDiffusion::floyd_steinberg()
+                       .remap_image(&view, PixelOptions::default(), &map, &colorspace, &mut []),
+                   Err(Error::SizeMismatch));
+    }
+
+    #[test]
+    fn serpentine_mirrors_odd_rows() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let map = ColorMap::new(&palette, &colorspace);
+        let black = colorspace.to_float(palette[0]);
+        let row: Vec<Colorf> =
+            (0..8).map(|i| colorspace.to_float(Color::new(i * 30, i * 30, i * 30, 255))).collect();
+        let ditherer = ErrorDiffusion::floyd_steinberg().serpentine();
+        let forward: Vec<usize> =
+            ditherer.remap(Box::new(row.clone().into_iter()), 8, &map, &colorspace).collect();
+        let image: Vec<Colorf> = (0..8).map(|_| black).chain(row.into_iter().rev()).collect();
+        let indices: Vec<usize> = ditherer.remap(Box::new(image.into_iter()), 8, &map, &colorspace)
+            .collect();
+        let backward: Vec<usize> = indices[8..].iter().rev().cloned().collect();
+        assert_eq!(backward, forward);
     }
 }
@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+
+/// The error type for the fallible operations of this crate.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// let colorspace = SimpleColorSpace::default();
+/// let ditherer = ditherer::None;
+/// assert_eq!(Remapper::new(&[], &colorspace, &ditherer).err(), Some(Error::EmptyPalette));
+///
+/// let palette = [Color::new(0, 0, 0, 255)];
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// assert_eq!(remapper.remap(&[], 0), Err(Error::ZeroWidth));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A palette without any colors was given.
+    EmptyPalette,
+    /// The palette has more colors (given as parameter) than can be indexed with `u8`s.
+    TooManyColors(usize),
+    /// An image width of zero was given.
+    ZeroWidth,
+    /// A color turned into NaN or infinite values when converted to quantization colorspace.
+    InvalidColor,
+    /// A per-pixel buffer does not have the same size as the image.
+    SizeMismatch,
+    /// An error diffusion kernel passes errors to pixels that have already been processed.
+    InvalidKernel,
+    /// A ditherer was configured with an unsupported matrix, texture or history size.
+    InvalidSize,
+    /// A palette index (given as parameter) to keep for a pixel is not in the palette.
+    InvalidIndex(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EmptyPalette => write!(f, "the palette is empty"),
+            Error::TooManyColors(n) => {
+                write!(f, "the palette has {} colors, but at most 256 are supported", n)
+            }
+            Error::ZeroWidth => write!(f, "the image width is zero"),
+            Error::InvalidColor => write!(f, "a color is not valid in quantization colorspace"),
+            Error::SizeMismatch => write!(f, "a buffer size does not match the image size"),
+            Error::InvalidKernel => {
+                write!(f, "the error diffusion kernel reaches already processed pixels")
+            }
+            Error::InvalidSize => write!(f, "the ditherer size parameter is not supported"),
+            Error::InvalidIndex(i) => write!(f, "the palette index {} is out of range", i),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::f64;
+
+    fn gradient(len: usize) -> Vec<Color> {
+        (0..len).map(|i| Color::new(i as u8, (i / 256) as u8 * 64, 128, 255)).collect()
+    }
+
+    #[test]
+    fn invalid_color() {
+        let colorspace = SimpleColorSpace { gamma: f64::NAN, ..Default::default() };
+        let image = gradient(64);
+        let histogram: Histogram = image.iter().cloned().collect();
+        assert_eq!(Quantizer::new(&histogram, &colorspace).err(), Some(Error::InvalidColor));
+        assert_eq!(generate_palette(&histogram, &colorspace, &optimizer::None, 16),
+                   Err(Error::InvalidColor));
+        assert_eq!(convert_to_indexed_with_colorspace(&image,
+                                                      8,
+                                                      16,
+                                                      &colorspace,
+                                                      &optimizer::None,
+                                                      &ditherer::None),
+                   Err(Error::InvalidColor));
+    }
+
+    #[test]
+    fn too_many_colors() {
+        let colorspace = SimpleColorSpace::default();
+        let image = gradient(300);
+        let remapper = Remapper::new(&image, &colorspace, &ditherer::None).unwrap();
+        assert_eq!(remapper.remap(&image, 30), Err(Error::TooManyColors(300)));
+        assert_eq!(remapper.remap_usize(&image, 30).unwrap().len(), 300);
+        let map = ColorMap::new(&image, &colorspace);
+        let remapper = Remapper::from_color_map(map, &colorspace, &ditherer::None).unwrap();
+        assert_eq!(remapper.remap(&image, 30), Err(Error::TooManyColors(300)));
+        // with few enough distinct colors the image is passed through without quantization
+        assert_eq!(convert_to_indexed(&image, 30, 300, &optimizer::None, &ditherer::None),
+                   Err(Error::TooManyColors(300)));
+        let image: Vec<Color> = gradient(600).into_iter().cycle().take(2000).collect();
+        assert_eq!(convert_to_indexed(&image, 40, 280, &optimizer::None, &ditherer::None),
+                   Err(Error::TooManyColors(280)));
+    }
+}
@@ -12,6 +12,21 @@ use super::*;
 ///
 /// The `Histogram::new()`, `histogram.extend(...)` method is useful when you want to create
 /// one palette for multiple distinct images, multiple frames of a GIF animation, etc.
+/// Histograms built separately (for example on different threads) can be combined using
+/// `Histogram::merge`. With the `parallel` feature, `Histogram` also implements
+/// `FromParallelIterator<Color>`.
+///
+/// Colors are stored exactly as given, so premultiplied alpha input is fine as long as the
+/// `ColorSpace` used for quantization has its alpha mode set to `AlphaMode::Premultiplied`.
+///
+/// For huge images with millions of distinct colors, `Histogram::with_bits_per_channel` creates
+/// a histogram that groups similar colors into buckets. This bounds the number of entries (and
+/// so the memory use and quantization time) while still keeping the average color of each
+/// bucket.
+///
+/// Counts don't have to be whole numbers: `Histogram::add_weighted` and
+/// `Histogram::from_importance_map` let some pixels count more than others, which gives
+/// important image regions more palette precision.
 ///
 /// # Examples
 /// ```
@@ -27,24 +42,132 @@ use super::*;
 /// let histogram: Histogram = image.pixels.iter().cloned().collect();
 /// ```
 pub struct Histogram {
-    data: HashMap<Color, usize>,
+    data: HashMap<Color, f64>,
+    sums: HashMap<Color, Colorf>, // the weighted color sums of each bucket, if bucketing
+    bits: u8,
 }
 
-/// A single float color in quantization color space with the number of times it occurs in the
-/// input image data.
+/// A single float color in quantization color space with the (weighted) number of times it
+/// occurs in the input image data.
 ///
 /// This type is used to hold histogram data during the actual quantization process. It's mostly
 /// used internally.
 #[derive(Clone)]
 pub struct ColorCount {
     pub color: Colorf,
-    pub count: usize,
+    pub count: f64,
 }
 
 impl Histogram {
     /// Returns a new, empty `Histogram`.
     pub fn new() -> Histogram {
-        Histogram { data: HashMap::new() }
+        Self::with_bits_per_channel(8)
+    }
+
+    /// Returns a new, empty `Histogram` that buckets colors by their top `bits` bits per channel.
+    ///
+    /// The histogram has at most `2^(4 * bits)` entries, each representing the average of all
+    /// colors added to its bucket. `bits` is clamped to the range 1 - 8, with 8 storing all
+    /// colors exactly like `Histogram::new()`.
+    ///
+    /// The average is taken of the rgba8 values as given, before the conversion to quantization
+    /// colorspace. For gamma encoded input this is slightly darker than averaging in the linear
+    /// quantization colorspace, but the difference stays small as the colors of a bucket are
+    /// close to each other.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// let mut histogram = Histogram::with_bits_per_channel(5);
+    /// histogram.extend(image.pixels.iter().cloned());
+    /// assert!(histogram.len() <= 1 << 20);
+    /// ```
+    pub fn with_bits_per_channel(bits: u8) -> Histogram {
+        Histogram {
+            data: HashMap::new(),
+            sums: HashMap::new(),
+            bits: bits.clamp(1, 8),
+        }
+    }
+
+    /// Returns the number of bits per channel colors are bucketed by.
+    pub fn bits_per_channel(&self) -> u8 {
+        self.bits
+    }
+
+    /// Adds `count` occurences of `color` to the histogram.
+    ///
+    /// A count of 0 still adds the color as an entry, without any weight, so the color is kept
+    /// when the histogram has few enough colors to be used as the palette directly. This differs
+    /// from `add_weighted`, which ignores zero weights.
+    pub fn add(&mut self, color: Color, count: usize) {
+        let weight = count as f64;
+        self.add_sum(color, to_colorf(color) * weight, weight);
+    }
+
+    /// Adds `color` to the histogram with the given weight.
+    ///
+    /// A weight of 1.0 is the same as a single occurence of the color. Unlike with `add`, colors
+    /// with a weight of 0.0 (or a negative or NaN weight) are ignored completely and don't get
+    /// an entry, so an importance of zero in `from_importance_map` masks pixels out of the
+    /// palette.
+    pub fn add_weighted(&mut self, color: Color, weight: f64) {
+        if weight > 0.0 && weight.is_finite() {
+            self.add_sum(color, to_colorf(color) * weight, weight);
+        }
+    }
+
+    /// Creates a histogram from an image and a per-pixel importance map.
+    ///
+    /// Each pixel is added with the weight given for it in `importance`, so for example a
+    /// saliency mask can be used to spend more of the palette on the important parts of an
+    /// image. Returns `Error::SizeMismatch` if the importance map doesn't have one entry per
+    /// pixel.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// // give the top half of the image four times the weight of the bottom half
+    /// let importance: Vec<f64> = (0..image.pixels.len())
+    ///     .map(|i| if i < image.pixels.len() / 2 { 4.0 } else { 1.0 })
+    ///     .collect();
+    /// let histogram = Histogram::from_importance_map(&image.pixels, &importance).unwrap();
+    /// ```
+    pub fn from_importance_map(image: &[Color], importance: &[f64]) -> Result<Histogram, Error> {
+        if image.len() != importance.len() {
+            return Err(Error::SizeMismatch);
+        }
+        let mut histogram = Histogram::new();
+        for (&color, &weight) in image.iter().zip(importance) {
+            histogram.add_weighted(color, weight);
+        }
+        Ok(histogram)
+    }
+
+    /// Adds all color counts of another histogram to this one.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// let (top, bottom) = image.pixels.split_at(image.pixels.len() / 2);
+    /// let mut histogram: Histogram = top.iter().cloned().collect();
+    /// histogram.merge(bottom.iter().cloned().collect());
+    /// ```
+    ///
+    /// If the two histograms bucket colors differently, the average color of each bucket of
+    /// `other` is added to the buckets of this histogram.
+    pub fn merge(&mut self, mut other: Histogram) {
+        if other.bits == self.bits && other.data.len() > self.data.len() {
+            ::std::mem::swap(&mut self.data, &mut other.data);
+            ::std::mem::swap(&mut self.sums, &mut other.sums);
+        }
+        for (&color, &count) in &other.data {
+            let mean = other.mean(color, count);
+            self.add_sum(to_color(mean), mean * count, count);
+        }
     }
 
     /// Converts the rgba8 `Histogram` to a Vec of `ColorCount` in quantization color space.
@@ -54,27 +177,90 @@ impl Histogram {
         self.data
             .iter()
             .map(|(color, count)| {
+                let color = if self.bits < 8 {
+                    let mean = self.mean(*color, *count) * (1.0 / 255.0);
+                    colorspace.to_linear(::colorspace::straight_alpha(mean,
+                                                                      colorspace.alpha_mode()))
+                } else {
+                    colorspace.to_float(*color)
+                };
                 ColorCount {
-                    color: colorspace.to_float(*color),
+                    color: color,
                     count: *count,
                 }
             })
             .collect()
     }
 
-    /// Returns an iterator over the histogram data.
-    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = (&Color, &usize)> + 'a> {
-        Box::new(self.data.iter())
+    /// Returns the number of distinct colors in the histogram.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns whether the histogram is empty.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns an iterator over the histogram colors and their counts.
+    ///
+    /// For bucketing histograms each entry is the rounded average color of a bucket.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = (Color, f64)> + 'a> {
+        if self.bits < 8 {
+            Box::new(self.data
+                .iter()
+                .map(move |(&color, &count)| (to_color(self.mean(color, count)), count)))
+        } else {
+            Box::new(self.data.iter().map(|(&color, &count)| (color, count)))
+        }
+    }
+
+    // Adds `weight` to the entry of `color`, with `sum` being the weighted sum of the colors
+    // represented by it.
+    fn add_sum(&mut self, color: Color, sum: Colorf, weight: f64) {
+        if self.bits < 8 {
+            let key = self.bucket(color);
+            *self.data.entry(key).or_insert(0.0) += weight;
+            *self.sums.entry(key).or_insert(Colorf::zero()) += sum;
+        } else {
+            *self.data.entry(color).or_insert(0.0) += weight;
+        }
+    }
+
+    fn bucket(&self, color: Color) -> Color {
+        let mask = 0xffu8 << (8 - self.bits);
+        Color::new(color.r & mask, color.g & mask, color.b & mask, color.a & mask)
+    }
+
+    // Returns the average color of an entry in 0.0 - 255.0 range.
+    fn mean(&self, color: Color, count: f64) -> Colorf {
+        match self.sums.get(&color) {
+            Some(&sum) if count > 0.0 => sum * (1.0 / count),
+            _ => to_colorf(color),
+        }
     }
 }
 
+fn to_colorf(color: Color) -> Colorf {
+    Colorf {
+        r: color.r as f64,
+        g: color.g as f64,
+        b: color.b as f64,
+        a: color.a as f64,
+    }
+}
+
+fn to_color(color: Colorf) -> Color {
+    let round = |v: f64| (v + 0.5).clamp(0.0, 255.0) as u8;
+    Color::new(round(color.r), round(color.g), round(color.b), round(color.a))
+}
+
 impl Extend<Color> for Histogram {
     fn extend<T>(&mut self, iter: T)
         where T: IntoIterator<Item = Color>
     {
         for pixel in iter {
-            let count = self.data.entry(pixel).or_insert(0);
-            *count += 1;
+            self.add_weighted(pixel, 1.0);
         }
     }
 }
@@ -89,6 +275,25 @@ impl FromIterator<Color> for Histogram {
     }
 }
 
+#[cfg(feature="parallel")]
+impl ::rayon::iter::FromParallelIterator<Color> for Histogram {
+    fn from_par_iter<T>(iter: T) -> Self
+        where T: ::rayon::iter::IntoParallelIterator<Item = Color>
+    {
+        use rayon::prelude::*;
+
+        iter.into_par_iter()
+            .fold(Histogram::new, |mut histogram, color| {
+                histogram.add(color, 1);
+                histogram
+            })
+            .reduce(Histogram::new, |mut a, b| {
+                a.merge(b);
+                a
+            })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::*;
@@ -98,6 +303,94 @@ mod tests {
         let mut hist: Histogram =
             [Color::new(10, 20, 30, 99), Color::new(0, 99, 0, 99)].iter().cloned().collect();
         hist.extend([Color::new(20, 0, 40, 99), Color::new(0, 99, 0, 99)].iter().cloned());
-        assert_eq!(*hist.data.get(&Color::new(0, 99, 0, 99)).unwrap(), 2usize);
+        assert_eq!(*hist.data.get(&Color::new(0, 99, 0, 99)).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn zero_weights() {
+        let mut hist = Histogram::new();
+        hist.add(Color::new(1, 2, 3, 4), 0);
+        hist.add_weighted(Color::new(5, 6, 7, 8), 0.0);
+        hist.add_weighted(Color::new(9, 9, 9, 9), -1.0);
+        hist.add_weighted(Color::new(9, 9, 9, 9), f64::NAN);
+        assert_eq!(hist.len(), 1);
+        assert_eq!(*hist.data.get(&Color::new(1, 2, 3, 4)).unwrap(), 0.0);
+
+        let mut hist = Histogram::with_bits_per_channel(4);
+        hist.add(Color::new(1, 2, 3, 4), 0);
+        assert_eq!(hist.iter().collect::<Vec<_>>(), vec![(Color::new(0, 0, 0, 0), 0.0)]);
+    }
+
+    #[test]
+    fn merge() {
+        let mut hist: Histogram = [Color::new(1, 2, 3, 4)].iter().cloned().collect();
+        hist.merge([Color::new(1, 2, 3, 4), Color::new(5, 6, 7, 8), Color::new(5, 6, 7, 8)]
+            .iter()
+            .cloned()
+            .collect());
+        assert_eq!(hist.len(), 2);
+        assert_eq!(*hist.data.get(&Color::new(1, 2, 3, 4)).unwrap(), 2.0);
+        assert_eq!(*hist.data.get(&Color::new(5, 6, 7, 8)).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn importance_map() {
+        let image = [Color::new(0, 0, 0, 255),
+                     Color::new(255, 255, 255, 255),
+                     Color::new(0, 0, 0, 255),
+                     Color::new(255, 0, 0, 255)];
+        let hist = Histogram::from_importance_map(&image, &[0.5, 3.0, 0.25, 0.0]).unwrap();
+        assert_eq!(hist.len(), 2);
+        assert_eq!(*hist.data.get(&Color::new(0, 0, 0, 255)).unwrap(), 0.75);
+        assert_eq!(*hist.data.get(&Color::new(255, 255, 255, 255)).unwrap(), 3.0);
+        assert_eq!(Histogram::from_importance_map(&image, &[1.0]).err(),
+                   Some(Error::SizeMismatch));
+
+        // the heavily weighted white should end up exactly in a two color palette
+        let colorspace = SimpleColorSpace::default();
+        let image = [Color::new(0, 0, 0, 255),
+                     Color::new(128, 128, 128, 255),
+                     Color::new(255, 255, 255, 255)];
+        let hist = Histogram::from_importance_map(&image, &[1.0, 1.0, 100.0]).unwrap();
+        let palette = Quantizer::create_palette(&hist, &colorspace, 2).unwrap();
+        assert!(palette.contains(&Color::new(255, 255, 255, 255)));
+    }
+
+    #[test]
+    fn bucketing() {
+        let mut hist = Histogram::with_bits_per_channel(5);
+        hist.extend((0..8).map(|i| Color::new(i, 100, 200, 255)));
+        hist.add(Color::new(255, 0, 0, 255), 2);
+        assert_eq!(hist.len(), 2);
+        let mut entries: Vec<(Color, f64)> = hist.iter().collect();
+        entries.sort_by_key(|&(c, _)| c.r);
+        assert_eq!(entries, vec![(Color::new(4, 100, 200, 255), 8.0),
+                                 (Color::new(255, 0, 0, 255), 2.0)]);
+
+        // the bucket average of the rgba8 values is kept precisely, without rounding
+        let colorspace = SimpleColorSpace::default();
+        let counts = hist.to_color_counts(&colorspace);
+        let expected = colorspace.to_linear(Colorf {
+            r: 3.5 / 255.0,
+            g: 100.0 / 255.0,
+            b: 200.0 / 255.0,
+            a: 1.0,
+        });
+        assert!(counts.iter().any(|e| (e.color - expected).abs() < 1e-9));
+
+        let mut exact: Histogram = [Color::new(7, 100, 200, 255)].iter().cloned().collect();
+        exact.merge(hist);
+        assert_eq!(exact.len(), 3);
+    }
+
+    #[cfg(feature="parallel")]
+    #[test]
+    fn from_par_iter() {
+        use rayon::prelude::*;
+
+        let pixels: Vec<Color> = (0..10240).map(|i| Color::new(i as u8, 0, 0, 255)).collect();
+        let hist: Histogram = pixels.par_iter().cloned().collect();
+        assert_eq!(hist.len(), 256);
+        assert_eq!(*hist.data.get(&Color::new(16, 0, 0, 255)).unwrap(), 40.0);
     }
 }
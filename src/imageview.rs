@@ -0,0 +1,132 @@
+use super::*;
+
+/// A read-only 2D view into a buffer of pixels.
+///
+/// Rows are `stride` pixels apart in the underlying buffer, so a view can also cover a
+/// sub-rectangle of a larger image or texture.
+///
+/// # Examples
+/// ```
+/// # use exoquant::*;
+/// let pixels = vec![Color::new(0, 0, 0, 255); 64 * 64];
+/// let texture = ImageView::new(&pixels, 64, 64, 64).unwrap();
+/// let tile = texture.sub_view(16, 16, 32, 8).unwrap();
+/// assert_eq!(tile.row(0).len(), 32);
+/// ```
+#[derive(Debug)]
+pub struct ImageView<'a, T: 'a> {
+    data: &'a [T],
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl<'a, T> Clone for ImageView<'a, T> {
+    fn clone(&self) -> ImageView<'a, T> {
+        *self
+    }
+}
+
+impl<'a, T> Copy for ImageView<'a, T> {}
+
+impl<'a, T: Copy> ImageView<'a, T> {
+    /// Creates a view of `width`x`height` pixels with rows `stride` pixels apart.
+    ///
+    /// Returns `Error::ZeroWidth` for a width of zero and `Error::SizeMismatch` if the stride
+    /// is smaller than the width or the buffer is too small.
+    pub fn new(data: &'a [T],
+               width: usize,
+               height: usize,
+               stride: usize)
+               -> Result<ImageView<'a, T>, Error> {
+        if width == 0 {
+            return Err(Error::ZeroWidth);
+        }
+        if stride < width || (height > 0 && data.len() < (height - 1) * stride + width) {
+            return Err(Error::SizeMismatch);
+        }
+        Ok(ImageView {
+            data: data,
+            width: width,
+            height: height,
+            stride: stride,
+        })
+    }
+
+    /// Creates a view of a tightly packed image buffer.
+    ///
+    /// The length of the buffer needs to be a multiple of `width`.
+    pub fn from_slice(data: &'a [T], width: usize) -> Result<ImageView<'a, T>, Error> {
+        if width > 0 && data.len() / width * width != data.len() {
+            return Err(Error::SizeMismatch);
+        }
+        ImageView::new(data, width, data.len() / width.max(1), width)
+    }
+
+    /// Returns the width of the view in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the view in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the distance between two rows in the underlying buffer.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the pixels of row `y`.
+    pub fn row(&self, y: usize) -> &'a [T] {
+        let start = y * self.stride;
+        &self.data[start..start + self.width]
+    }
+
+    /// Returns the pixel at the given position.
+    pub fn get(&self, x: usize, y: usize) -> T {
+        self.data[x + y * self.stride]
+    }
+
+    /// Returns a view of a sub-rectangle of this view.
+    ///
+    /// Returns `Error::SizeMismatch` if the rectangle is not fully inside this view.
+    pub fn sub_view(&self,
+                    x: usize,
+                    y: usize,
+                    width: usize,
+                    height: usize)
+                    -> Result<ImageView<'a, T>, Error> {
+        if x + width > self.width || y + height > self.height {
+            return Err(Error::SizeMismatch);
+        }
+        let start = (x + y * self.stride).min(self.data.len());
+        ImageView::new(&self.data[start..], width, height, self.stride)
+    }
+
+    /// Returns an iterator over all pixels in row-major order.
+    pub fn pixels(&self) -> Box<Iterator<Item = T> + 'a> {
+        let view = *self;
+        Box::new((0..self.height).flat_map(move |y| view.row(y).iter().cloned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn strided_sub_view() {
+        let data: Vec<usize> = (0..40).collect();
+        let view = ImageView::new(&data, 6, 4, 10).unwrap();
+        assert_eq!(view.row(1), &[10, 11, 12, 13, 14, 15]);
+        let sub = view.sub_view(2, 1, 3, 3).unwrap();
+        assert_eq!(sub.pixels().collect::<Vec<_>>(),
+                   vec![12, 13, 14, 22, 23, 24, 32, 33, 34]);
+        assert_eq!(sub.get(1, 2), 33);
+        assert!(view.sub_view(4, 0, 3, 1).is_err());
+        assert_eq!(ImageView::new(&data, 10, 5, 10).err(), Some(Error::SizeMismatch));
+        assert_eq!(ImageView::from_slice(&data, 7).err(), Some(Error::SizeMismatch));
+    }
+}
@@ -15,7 +15,7 @@
 //! let image = testdata::test_image();
 //!
 //! let (palette, indexed_data) = convert_to_indexed(&image.pixels, image.width, 256,
-//!   &optimizer::KMeans, &ditherer::FloydSteinberg::new());
+//!   &optimizer::KMeans, &ditherer::FloydSteinberg::new()).unwrap();
 //! ```
 //!
 //! # Low-Level API:
@@ -39,7 +39,7 @@
 //!
 //! let colorspace = SimpleColorSpace::default();
 //! let optimizer = optimizer::KMeans;
-//! let mut quantizer = Quantizer::new(&histogram, &colorspace);
+//! let mut quantizer = Quantizer::new(&histogram, &colorspace).unwrap();
 //! while quantizer.num_colors() < 256 {
 //!   quantizer.step();
 //!   // very optional optimization, !very slow!
@@ -54,10 +54,13 @@
 //! let palette = optimizer.optimize_palette(&colorspace, &palette, &histogram, 16);
 //!
 //! let ditherer = ditherer::FloydSteinberg::new();
-//! let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-//! let indexed_data = remapper.remap(&image.pixels, image.width);
+//! let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+//! let indexed_data = remapper.remap(&image.pixels, image.width).unwrap();
 //! ```
 
+#[cfg(feature="parallel")]
+extern crate rayon;
+
 mod color;
 mod histogram;
 mod quantizer;
@@ -67,19 +70,29 @@ pub mod optimizer;
 mod colorspace;
 mod palettesort;
 mod basicapi;
+mod error;
+mod stats;
+mod imageview;
 #[cfg(feature="random-sample")]
 pub mod random_sample;
 pub mod ditherer;
 #[doc(hidden)]
 pub mod testdata;
 
-pub use basicapi::{convert_to_indexed, generate_palette};
+pub use basicapi::{convert_to_indexed, convert_to_indexed_with_colorspace,
+                   convert_to_indexed_binary_alpha, generate_palette,
+                   generate_palette_binary_alpha, generate_palette_for_quality,
+                   QualityTarget};
 pub use color::*;
 pub use colormap::ColorMap;
-pub use colorspace::{ColorSpace, SimpleColorSpace};
+pub use error::Error;
+pub use colorspace::{ColorSpace, SimpleColorSpace, OklabColorSpace, LabColorSpace,
+                     TransferFunction, AlphaMode};
 pub use histogram::*;
+pub use imageview::ImageView;
 pub use palettesort::sort_palette;
 pub use quantizer::Quantizer;
 #[cfg(feature="random-sample")]
 pub use random_sample::RandomSample;
 pub use remapper::Remapper;
+pub use stats::{ErrorStats, RemapStats};
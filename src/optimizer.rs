@@ -12,6 +12,9 @@ pub trait Optimizer {
 
     /// Optimize a given palette with a number of K-Means iteration.
     ///
+    /// Palettes with locked colors are optimized with `optimize_palette_with_fixed` instead,
+    /// which keeps the signature of this function unchanged.
+    ///
     /// # Examples:
     /// ```
     /// # use exoquant::*;
@@ -19,7 +22,7 @@ pub trait Optimizer {
     /// # let image = testdata::test_image();
     /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
     /// # let colorspace = SimpleColorSpace::default();
-    /// let palette = Quantizer::create_palette(&histogram, &colorspace, 256);
+    /// let palette = Quantizer::create_palette(&histogram, &colorspace, 256).unwrap();
     /// let palette = optimizer::KMeans.optimize_palette(&colorspace, &palette,
     ///   &histogram, 16);
     /// ```
@@ -29,15 +32,55 @@ pub trait Optimizer {
                         histogram: &Histogram,
                         num_iterations: usize)
                         -> Vec<Color> {
+        self.optimize_palette_with_fixed(colorspace, &[], palette, histogram, num_iterations)
+    }
+
+    /// Optimize a given palette with a number of locked colors.
+    ///
+    /// The `fixed` colors take part in the optimization, so they account for the histogram
+    /// colors closest to them, but are never moved. The returned palette starts with the
+    /// unchanged fixed colors followed by the optimized `palette` colors.
+    ///
+    /// # Examples:
+    /// ```
+    /// # use exoquant::*;
+    /// # use exoquant::optimizer::Optimizer;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// # let colorspace = SimpleColorSpace::default();
+    /// let fixed = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+    /// let mut quantizer = Quantizer::with_fixed_colors(&histogram, &colorspace, &fixed).unwrap();
+    /// while quantizer.num_colors() < 64 {
+    ///   quantizer.step();
+    /// }
+    /// let palette = quantizer.colors(&colorspace);
+    /// let palette = optimizer::KMeans.optimize_palette_with_fixed(&colorspace, &fixed,
+    ///   &palette[fixed.len()..], &histogram, 16);
+    /// ```
+    fn optimize_palette_with_fixed(&self,
+                                   colorspace: &ColorSpace,
+                                   fixed: &[Color],
+                                   palette: &[Color],
+                                   histogram: &Histogram,
+                                   num_iterations: usize)
+                                   -> Vec<Color> {
         if self.is_noop() {
-            return palette.iter().cloned().collect();
+            return fixed.iter().chain(palette.iter()).cloned().collect();
         }
         let hist = histogram.to_color_counts(colorspace);
-        let mut colors = palette.iter().map(|c| colorspace.to_float(*c)).collect();
+        let fixed_colors: Vec<Colorf> = fixed.iter().map(|c| colorspace.to_float(*c)).collect();
+        let mut colors: Vec<Colorf> = fixed_colors.iter()
+            .cloned()
+            .chain(palette.iter().map(|c| colorspace.to_float(*c)))
+            .collect();
         for _ in 0..num_iterations {
             colors = self.step(colors, &hist);
+            colors[..fixed.len()].copy_from_slice(&fixed_colors);
         }
-        colors.iter().map(|&c| colorspace.from_float(c)).collect()
+        fixed.iter()
+            .cloned()
+            .chain(colors[fixed.len()..].iter().map(|&c| colorspace.from_float(c)))
+            .collect()
     }
 
     /// Returns whether this Optimizer is a No-op implementation.
@@ -87,11 +130,13 @@ impl Optimizer for KMeans {
         for entry in histogram {
             let index = map.find_nearest(entry.color);
             let mut cluster = &mut clusters[index];
-            cluster.sum += entry.color * entry.count as f64;
-            cluster.weight += entry.count as f64;
+            cluster.sum += entry.color * entry.count;
+            cluster.weight += entry.count;
         }
         clusters.iter()
-            .map(|cluster| cluster.sum * (1.0 / cluster.weight.max(1.0)))
+            .map(|cluster| {
+                cluster.sum * (1.0 / if cluster.weight > 0.0 { cluster.weight } else { 1.0 })
+            })
             .collect()
     }
 }
@@ -136,7 +181,7 @@ impl Optimizer for WeightedKMeans {
                 color = entry.color + diff;
             }
             let mut cluster = &mut clusters[index];
-            let weight = entry.count as f64 * error_sum.dot(&error_sum);
+            let weight = entry.count * error_sum.dot(&error_sum);
             cluster.sum += entry.color * weight;
             cluster.weight += weight;
         }
@@ -151,3 +196,30 @@ impl Optimizer for WeightedKMeans {
         colors
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_colors_are_locked() {
+        let colorspace = SimpleColorSpace::default();
+        let mut histogram = Histogram::new();
+        for i in 0..16 {
+            histogram.add(Color::new(230 + i, 20, 20, 255), 10);
+            histogram.add(Color::new(20, 20, 230 + i, 255), 10);
+        }
+        let fixed = [Color::new(255, 0, 0, 255)];
+        let palette = [Color::new(128, 128, 128, 255)];
+        let optimizers: [&Optimizer; 2] = [&KMeans, &WeightedKMeans];
+        for optimizer in optimizers.iter() {
+            let optimized =
+                optimizer.optimize_palette_with_fixed(&colorspace, &fixed, &palette, &histogram, 8);
+            assert_eq!(optimized.len(), 2);
+            assert_eq!(optimized[0], fixed[0]);
+            // the reds are represented by the fixed color, so the free entry moves to the blues
+            let free = optimized[1];
+            assert!(free.r < 40 && free.g < 40 && free.b > 200);
+        }
+    }
+}
@@ -6,6 +6,9 @@ use ::color::Color;
 /// look a lot more tidy.
 pub fn sort_palette(palette: &Vec<Color>, image: &Vec<u8>) -> (Vec<Color>, Vec<u8>) {
     let num_colors = palette.len();
+    if num_colors == 0 {
+        return (Vec::new(), image.clone());
+    }
     let mut counts: Vec<usize> = (0..num_colors).map(|_| 0).collect();
     let mut neighbors: Vec<Vec<usize>> =
         (0..num_colors).map(|_| (0..num_colors).map(|_| 0).collect()).collect();
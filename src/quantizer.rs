@@ -1,9 +1,13 @@
+use std::cmp::Ordering;
+
 use super::*;
 use optimizer::Optimizer;
 
 struct QuantizerNode {
     histogram: Vec<ColorCount>, // a histogram of the colors represented by this node
     avg: Colorf, // the average color of this node
+    count: f64, // the (weighted) number of pixels represented by this node
+    variance: f64, // the summed squared distance of all pixels to the average color
     vdif: f64, // the improvement to the total variance when splitting this node
     split: usize, // the best index to split this node at
 }
@@ -11,44 +15,46 @@ struct QuantizerNode {
 impl QuantizerNode {
     fn new(mut histogram: Vec<ColorCount>) -> QuantizerNode {
         // First calculate the color average and variance over the histogram
-        let mut n = 0usize;
+        let mut n = 0.0;
         let mut fsum = Colorf::zero();
         let mut fsum2 = Colorf::zero();
 
         for entry in &histogram {
             n += entry.count;
-            fsum += entry.color * entry.count as f64;
-            fsum2 += entry.color * entry.color * entry.count as f64;
+            fsum += entry.color * entry.count;
+            fsum2 += entry.color * entry.color * entry.count;
         }
 
-        if n == 0 {
+        if n <= 0.0 {
             return QuantizerNode {
                 histogram: histogram,
                 avg: Colorf::zero(),
+                count: 0.0,
+                variance: 0.0,
                 vdif: 0.0,
                 split: 0,
             };
         }
 
-        let avg = fsum * (1.0 / n as f64);
+        let avg = fsum * (1.0 / n);
         let vc = fsum2 - fsum * avg;
         let v = vc.r + vc.g + vc.b + vc.a;
 
         // Next sort histogram by the channel with the largest variance
         if vc.r > vc.g && vc.r > vc.b && vc.r > vc.a {
-            histogram.sort_by(|a, b| a.color.r.partial_cmp(&b.color.r).unwrap());
+            histogram.sort_by(|a, b| a.color.r.partial_cmp(&b.color.r).unwrap_or(Ordering::Equal));
         } else if vc.g > vc.b && vc.g > vc.a {
-            histogram.sort_by(|a, b| a.color.g.partial_cmp(&b.color.g).unwrap());
+            histogram.sort_by(|a, b| a.color.g.partial_cmp(&b.color.g).unwrap_or(Ordering::Equal));
         } else if vc.b > vc.a {
-            histogram.sort_by(|a, b| a.color.b.partial_cmp(&b.color.b).unwrap());
+            histogram.sort_by(|a, b| a.color.b.partial_cmp(&b.color.b).unwrap_or(Ordering::Equal));
         } else {
-            histogram.sort_by(|a, b| a.color.a.partial_cmp(&b.color.a).unwrap());
+            histogram.sort_by(|a, b| a.color.a.partial_cmp(&b.color.a).unwrap_or(Ordering::Equal));
         }
 
         // Determine primary vector of distribution in the histogram
         let mut dir = Colorf::zero();
         for entry in &histogram {
-            let mut tmp = (entry.color - avg) * entry.count as f64;
+            let mut tmp = (entry.color - avg) * entry.count;
             if tmp.dot(&dir) < 0.0 {
                 tmp *= -1.0;
             }
@@ -61,23 +67,25 @@ impl QuantizerNode {
         };
 
         // Now sort histogram by primary vector
-        histogram.sort_by(|a, b| a.color.dot(&dir).partial_cmp(&b.color.dot(&dir)).unwrap());
+        histogram.sort_by(|a, b| {
+            a.color.dot(&dir).partial_cmp(&b.color.dot(&dir)).unwrap_or(Ordering::Equal)
+        });
 
         // Find split index that results in lowest total variance
         let mut sum = Colorf::zero();
         let mut sum2 = Colorf::zero();
         let mut vdif = -v;
-        let mut n2 = 0;
+        let mut n2 = 0.0;
         let mut split = 0usize;
         for (i, entry) in histogram.iter().enumerate() {
             n2 += entry.count;
-            sum += entry.color * entry.count as f64;
-            sum2 += entry.color * entry.color * entry.count as f64;
+            sum += entry.color * entry.count;
+            sum2 += entry.color * entry.color * entry.count;
 
-            if n2 < n {
-                let tmp = sum2 - sum * sum * (1.0 / n2 as f64);
+            if i + 1 < histogram.len() && n2 > 0.0 && n2 < n {
+                let tmp = sum2 - sum * sum * (1.0 / n2);
                 let dif_sum = fsum - sum;
-                let tmp2 = (fsum2 - sum2) - dif_sum * dif_sum * (1.0 / (n - n2) as f64);
+                let tmp2 = (fsum2 - sum2) - dif_sum * dif_sum * (1.0 / (n - n2));
                 let nv = tmp.r + tmp.g + tmp.b + tmp.a + tmp2.r + tmp2.g + tmp2.b + tmp2.a;
                 if -nv > vdif {
                     vdif = -nv;
@@ -89,12 +97,21 @@ impl QuantizerNode {
         QuantizerNode {
             histogram: histogram,
             avg: avg,
+            count: n,
+            variance: v.max(0.0),
             vdif: vdif + v,
             split: split,
         }
     }
 }
 
+// A locked palette color together with the histogram entries it represents.
+struct FixedColor {
+    color: Color,
+    float: Colorf,
+    histogram: Vec<ColorCount>,
+}
+
 /// The main color quantizer state.
 ///
 /// The Quantizer is used to find a palette of colors that represent the colors in an input
@@ -116,7 +133,7 @@ impl QuantizerNode {
 /// # let image = testdata::test_image();
 /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
 /// # let colorspace = SimpleColorSpace::default();
-/// let mut quantizer = Quantizer::new(&histogram, &colorspace);
+/// let mut quantizer = Quantizer::new(&histogram, &colorspace).unwrap();
 /// while quantizer.num_colors() < 256 {
 ///   quantizer.step();
 /// }
@@ -128,61 +145,189 @@ impl QuantizerNode {
 /// # let image = testdata::test_image();
 /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
 /// # let colorspace = SimpleColorSpace::default();
-/// let palette = Quantizer::create_palette(&histogram, &colorspace, 256);
+/// let palette = Quantizer::create_palette(&histogram, &colorspace, 256).unwrap();
 /// ```
-pub struct Quantizer(Vec<QuantizerNode>);
+pub struct Quantizer {
+    nodes: Vec<QuantizerNode>,
+    fixed: Vec<FixedColor>,
+    fixed_map: Option<ColorMap>,
+}
 
 impl Quantizer {
     /// Create a new Quantizer state for the given histogram.
-    pub fn new<T: ColorSpace>(histogram: &::histogram::Histogram, colorspace: &T) -> Quantizer {
-        let hist = histogram.to_color_counts(colorspace);
-        Quantizer(vec![QuantizerNode::new(hist)])
+    ///
+    /// Returns `Error::InvalidColor` if any of the colors can't be represented in the
+    /// colorspace. Locked palette colors are passed to `Quantizer::with_fixed_colors` instead,
+    /// which keeps the signature of this function unchanged.
+    pub fn new<T: ColorSpace>(histogram: &::histogram::Histogram,
+                              colorspace: &T)
+                              -> Result<Quantizer, Error> {
+        Self::with_fixed_colors(histogram, colorspace, &[])
+    }
+
+    /// Create a new Quantizer state with a number of locked palette colors.
+    ///
+    /// The fixed colors are always part of the palette and absorb the histogram colors closest
+    /// to them, the quantizer only splits the remaining colors into new palette entries.
+    /// `quantizer.colors()` returns the fixed colors unchanged at the start of the palette.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// # let colorspace = SimpleColorSpace::default();
+    /// let fixed = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+    /// let mut quantizer = Quantizer::with_fixed_colors(&histogram, &colorspace, &fixed).unwrap();
+    /// while quantizer.num_colors() < 16 {
+    ///   quantizer.step();
+    /// }
+    /// let palette = quantizer.colors(&colorspace);
+    /// assert_eq!(&palette[..2], &fixed[..]);
+    /// ```
+    pub fn with_fixed_colors<T: ColorSpace>(histogram: &::histogram::Histogram,
+                                            colorspace: &T,
+                                            fixed: &[Color])
+                                            -> Result<Quantizer, Error> {
+        let fixed_floats: Vec<Colorf> = fixed.iter().map(|&c| colorspace.to_float(c)).collect();
+        let histogram = histogram.to_color_counts(colorspace);
+        if !fixed_floats.iter().chain(histogram.iter().map(|e| &e.color)).all(|c| c.is_finite()) {
+            return Err(Error::InvalidColor);
+        }
+        let mut quantizer = Quantizer {
+            nodes: Vec::new(),
+            fixed: fixed.iter()
+                .zip(fixed_floats.iter())
+                .map(|(&color, &float)| {
+                    FixedColor {
+                        color: color,
+                        float: float,
+                        histogram: Vec::new(),
+                    }
+                })
+                .collect(),
+            fixed_map: if fixed.is_empty() {
+                None
+            } else {
+                Some(ColorMap::from_float_colors(fixed_floats))
+            },
+        };
+        let node = quantizer.new_node(histogram);
+        quantizer.nodes.push(node);
+        Ok(quantizer)
     }
 
     /// A shortcut function to directly create a palette from a histogram.
     pub fn create_palette<T: ColorSpace>(histogram: &::histogram::Histogram,
                                          colorspace: &T,
                                          num_colors: usize)
-                                         -> Vec<Color> {
-        let mut quantizer = Self::new(histogram, colorspace);
+                                         -> Result<Vec<Color>, Error> {
+        let mut quantizer = Self::new(histogram, colorspace)?;
         while quantizer.num_colors() < num_colors {
             quantizer.step();
         }
-        quantizer.colors(colorspace)
+        Ok(quantizer.colors(colorspace))
     }
 
     /// Returns the current number of colors in this Quantizer state.
     ///
-    /// This starts off at 1 and increases by 1 for each call to `quantizer.step()`.
+    /// This starts off at 1 (plus the number of fixed colors) and increases by 1 for each call
+    /// to `quantizer.step()`.
     pub fn num_colors(&self) -> usize {
-        self.0.len()
+        self.fixed.len() + self.nodes.len()
+    }
+
+    /// Returns the mean squared error of the current quantizer state.
+    ///
+    /// This is the squared distance of each histogram color to the palette color representing
+    /// it, averaged over all pixels and the four color channels, measured in the quantization
+    /// colorspace. It is tracked during quantization, so calling this is cheap.
+    pub fn mse(&self) -> f64 {
+        let mut count = 0.0;
+        let mut error = 0.0;
+        for node in &self.nodes {
+            count += node.count;
+            error += node.variance;
+        }
+        for fixed in &self.fixed {
+            for entry in &fixed.histogram {
+                let diff = entry.color - fixed.float;
+                count += entry.count;
+                error += diff.dot(&diff) * entry.count;
+            }
+        }
+        if count <= 0.0 {
+            0.0
+        } else {
+            error / (count * 4.0)
+        }
+    }
+
+    /// Returns the peak signal-to-noise ratio in dB of the current quantizer state.
+    ///
+    /// This is calculated from `mse()` with a peak value of 1.0.
+    pub fn psnr(&self) -> f64 {
+        -10.0 * self.mse().log10()
     }
 
     /// Run one quantization step which increases the `num_colors()` by one.
     pub fn step(&mut self) {
-        let (new_node1, new_node2) = {
+        let (colors1, colors2) = {
             let node = {
                 let mut best_i = 0;
                 let mut best_e = 0.0;
-                for i in 0..self.0.len() {
-                    if self.0[i].vdif >= best_e {
-                        best_e = self.0[i].vdif;
+                for i in 0..self.nodes.len() {
+                    if self.nodes[i].vdif >= best_e {
+                        best_e = self.nodes[i].vdif;
                         best_i = i;
                     }
                 }
-                self.0.swap_remove(best_i)
+                self.nodes.swap_remove(best_i)
             };
             let mut colors1 = node.histogram;
             let colors2 = colors1.split_off(node.split);
-            (QuantizerNode::new(colors1), QuantizerNode::new(colors2))
+            (colors1, colors2)
         };
-        self.0.push(new_node1);
-        self.0.push(new_node2);
+        let new_node1 = self.new_node(colors1);
+        let new_node2 = self.new_node(colors2);
+        self.nodes.push(new_node1);
+        self.nodes.push(new_node2);
+    }
+
+    // Creates a new node, handing any histogram entries that are closer to one of the fixed
+    // colors than to the average of the node over to that fixed color.
+    fn new_node(&mut self, histogram: Vec<ColorCount>) -> QuantizerNode {
+        let map = match self.fixed_map {
+            Some(ref map) => map,
+            None => return QuantizerNode::new(histogram),
+        };
+        let mut n = 0.0;
+        let mut sum = Colorf::zero();
+        for entry in &histogram {
+            n += entry.count;
+            sum += entry.color * entry.count;
+        }
+        let avg = sum * (1.0 / if n > 0.0 { n } else { 1.0 });
+        let mut remaining = Vec::with_capacity(histogram.len());
+        for entry in histogram {
+            let index = map.find_nearest(entry.color);
+            let fixed = &mut self.fixed[index];
+            if (entry.color - fixed.float).abs() < (entry.color - avg).abs() {
+                fixed.histogram.push(entry);
+            } else {
+                remaining.push(entry);
+            }
+        }
+        QuantizerNode::new(remaining)
     }
 
     /// Returns colors the current Quantizer state represents..
     pub fn colors<T: ColorSpace>(&self, colorspace: &T) -> Vec<Color> {
-        self.0.iter().map(|node| colorspace.from_float(node.avg)).collect()
+        self.fixed
+            .iter()
+            .map(|fixed| fixed.color)
+            .chain(self.nodes.iter().map(|node| colorspace.from_float(node.avg)))
+            .collect()
     }
 
     /// Run a number of K-Means iteration on the current quantizer state.
@@ -192,6 +337,8 @@ impl Quantizer {
     /// sufficient to only run optimizations on the final palette, in which case
     /// `optimizer.optimize_palette` is slightly less expensive.
     ///
+    /// Fixed colors are never moved by the optimization.
+    ///
     /// # Examples
     /// ```
     /// # use exoquant::*;
@@ -199,7 +346,7 @@ impl Quantizer {
     /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
     /// # let colorspace = SimpleColorSpace::default();
     /// let optimizer = optimizer::KMeans;
-    /// let mut quantizer = Quantizer::new(&histogram, &colorspace);
+    /// let mut quantizer = Quantizer::new(&histogram, &colorspace).unwrap();
     /// while quantizer.num_colors() < 256 {
     ///   quantizer.step();
     ///   if quantizer.num_colors() % 32 == 0 {
@@ -212,18 +359,63 @@ impl Quantizer {
         if optimizer.is_noop() {
             return self;
         }
-        let (mut colors, histograms): (Vec<Colorf>, Vec<Vec<ColorCount>>) =
-            self.0.into_iter().map(|node| (node.avg, node.histogram)).unzip();
-        let histogram: Vec<ColorCount> =
-            histograms.iter().flat_map(|h| h.iter().cloned()).collect();
+        let num_fixed = self.fixed.len();
+        let mut fixed = self.fixed;
+        let mut colors: Vec<Colorf> = fixed.iter().map(|f| f.float).collect();
+        let mut histogram: Vec<ColorCount> = Vec::new();
+        for f in &mut fixed {
+            histogram.append(&mut f.histogram);
+        }
+        for node in self.nodes {
+            colors.push(node.avg);
+            histogram.extend(node.histogram);
+        }
         for _ in 0..num_iterations {
             colors = optimizer.step(colors, &histogram);
+            for (color, f) in colors.iter_mut().zip(fixed.iter()) {
+                *color = f.float;
+            }
         }
         let mut histograms: Vec<Vec<ColorCount>> = (0..colors.len()).map(|_| Vec::new()).collect();
         let map = ColorMap::from_float_colors(colors);
         for color in histogram {
             histograms[map.find_nearest(color.color)].push(color);
         }
-        Quantizer(histograms.into_iter().map(|h| QuantizerNode::new(h)).collect())
+        let nodes = histograms.split_off(num_fixed);
+        for (f, h) in fixed.iter_mut().zip(histograms) {
+            f.histogram = h;
+        }
+        Quantizer {
+            nodes: nodes.into_iter().map(|h| QuantizerNode::new(h)).collect(),
+            fixed: fixed,
+            fixed_map: self.fixed_map,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn fixed_colors_absorb_near_duplicates() {
+        let colorspace = SimpleColorSpace::default();
+        let mut histogram = Histogram::new();
+        histogram.add(Color::new(2, 2, 2, 255), 1000);
+        histogram.add(Color::new(250, 20, 20, 255), 10);
+        histogram.add(Color::new(20, 20, 250, 255), 10);
+        let fixed = [Color::new(0, 0, 0, 255)];
+        let mut quantizer = Quantizer::with_fixed_colors(&histogram, &colorspace, &fixed).unwrap();
+        while quantizer.num_colors() < 3 {
+            quantizer.step();
+        }
+        let palette = quantizer.colors(&colorspace);
+        assert_eq!(palette[0], fixed[0]);
+        // the near black is represented by the fixed black, so both free entries go to the
+        // red and blue
+        let mut free: Vec<Color> = palette[1..].to_vec();
+        free.sort_by_key(|c| c.r);
+        assert!(free[0].b > 200 && free[0].r < 40);
+        assert!(free[1].r > 200 && free[1].b < 40);
     }
 }
@@ -1,17 +1,34 @@
 //! Random sampling of iterators
+//!
+//! Sampling a large image before building the histogram speeds up palette generation a lot.
+//! For reproducible palettes use one of the deterministic variants: `random_sample_seeded`,
+//! `random_sample_with_rng` with a seeded `Rng`, or the `strided_sample` and
+//! `stratified_sample` methods, which also cover the image more evenly.
+//!
+//! # Examples
+//! ```
+//! # use exoquant::*;
+//! # let image = testdata::test_image();
+//! let a: Histogram = image.pixels.iter().cloned().random_sample_seeded(0.25, 42).collect();
+//! let b: Histogram = image.pixels.iter().cloned().random_sample_seeded(0.25, 42).collect();
+//! assert_eq!(a.len(), b.len());
+//! ```
 
 extern crate rand;
 
-pub struct RandomSampleIter<'a, T: 'a + Iterator> {
+use self::rand::{Rng, SeedableRng, ThreadRng, XorShiftRng};
+
+pub struct RandomSampleIter<'a, T: 'a + Iterator, R: Rng = ThreadRng> {
     iter: &'a mut T,
     prob: f32,
+    rng: R,
 }
 
-impl<'a, T: Iterator> Iterator for RandomSampleIter<'a, T> {
+impl<'a, T: Iterator, R: Rng> Iterator for RandomSampleIter<'a, T, R> {
     type Item = T::Item;
     fn next(&mut self) -> Option<T::Item> {
         while let Some(color) = self.iter.next() {
-            if self.prob >= rand::random() {
+            if self.prob >= self.rng.gen() {
                 return Some(color);
             }
         }
@@ -19,17 +36,173 @@ impl<'a, T: Iterator> Iterator for RandomSampleIter<'a, T> {
     }
 }
 
-/// Adds a `random_sample(probability)` method to iterators.
+pub struct StridedSampleIter<'a, T: 'a + Iterator> {
+    iter: &'a mut T,
+    stride: usize,
+}
+
+impl<'a, T: Iterator> Iterator for StridedSampleIter<'a, T> {
+    type Item = T::Item;
+    fn next(&mut self) -> Option<T::Item> {
+        let item = self.iter.next();
+        for _ in 1..self.stride {
+            if self.iter.next().is_none() {
+                break;
+            }
+        }
+        item
+    }
+}
+
+pub struct StratifiedSampleIter<'a, T: 'a + Iterator, R: Rng> {
+    iter: &'a mut T,
+    stride: usize,
+    rng: R,
+}
+
+impl<'a, T: Iterator, R: Rng> Iterator for StratifiedSampleIter<'a, T, R> {
+    type Item = T::Item;
+    fn next(&mut self) -> Option<T::Item> {
+        // reservoir sampling picks each item of the block with the same probability, even if
+        // the last block is cut short
+        let mut result = Option::None;
+        for i in 0..self.stride {
+            match self.iter.next() {
+                Some(item) => {
+                    if self.rng.gen_range(0, i + 1) == 0 {
+                        result = Some(item);
+                    }
+                }
+                Option::None => break,
+            }
+        }
+        result
+    }
+}
+
+/// Adds random and deterministic sampling methods to iterators.
 pub trait RandomSample<T: Iterator> {
     /// Returns a new iterator that randomly samples the original iterator.
+    ///
+    /// This uses the thread local random number generator, so the result differs between runs.
     fn random_sample<'a>(&'a mut self, prob: f32) -> RandomSampleIter<'a, T>;
+
+    /// Returns a new iterator that samples the original iterator using the given `Rng`.
+    fn random_sample_with_rng<'a, R: Rng>(&'a mut self,
+                                          prob: f32,
+                                          rng: R)
+                                          -> RandomSampleIter<'a, T, R>;
+
+    /// Returns a new iterator that randomly samples the original iterator, always picking the
+    /// same items for the same `seed`.
+    fn random_sample_seeded<'a>(&'a mut self,
+                                prob: f32,
+                                seed: u32)
+                                -> RandomSampleIter<'a, T, SeededRng>;
+
+    /// Returns a new iterator that returns every `stride`th item of the original iterator,
+    /// starting with the first.
+    fn strided_sample<'a>(&'a mut self, stride: usize) -> StridedSampleIter<'a, T>;
+
+    /// Returns a new iterator that returns one randomly chosen item out of each consecutive
+    /// block of `stride` items of the original iterator, including a final shorter block.
+    ///
+    /// This avoids the aliasing of `strided_sample` with regular image content while still
+    /// covering the whole input evenly.
+    fn stratified_sample<'a, R: Rng>(&'a mut self,
+                                     stride: usize,
+                                     rng: R)
+                                     -> StratifiedSampleIter<'a, T, R>;
 }
 
 impl<T: Iterator> RandomSample<T> for T {
     fn random_sample<'a>(&'a mut self, prob: f32) -> RandomSampleIter<'a, Self> {
+        self.random_sample_with_rng(prob, rand::thread_rng())
+    }
+
+    fn random_sample_with_rng<'a, R: Rng>(&'a mut self,
+                                          prob: f32,
+                                          rng: R)
+                                          -> RandomSampleIter<'a, Self, R> {
         RandomSampleIter {
             iter: self,
             prob: prob,
+            rng: rng,
+        }
+    }
+
+    fn random_sample_seeded<'a>(&'a mut self,
+                                prob: f32,
+                                seed: u32)
+                                -> RandomSampleIter<'a, Self, SeededRng> {
+        self.random_sample_with_rng(prob, SeededRng::new(seed))
+    }
+
+    fn strided_sample<'a>(&'a mut self, stride: usize) -> StridedSampleIter<'a, Self> {
+        StridedSampleIter {
+            iter: self,
+            stride: stride.max(1),
+        }
+    }
+
+    fn stratified_sample<'a, R: Rng>(&'a mut self,
+                                     stride: usize,
+                                     rng: R)
+                                     -> StratifiedSampleIter<'a, Self, R> {
+        StratifiedSampleIter {
+            iter: self,
+            stride: stride.max(1),
+            rng: rng,
+        }
+    }
+}
+
+/// A fast, seeded random number generator for reproducible sampling.
+///
+/// The generator algorithm is an implementation detail and may change between versions, but
+/// stays the same for a given version of this crate.
+pub struct SeededRng(XorShiftRng);
+
+impl SeededRng {
+    /// Returns a random number generator initialized from `seed`.
+    pub fn new(seed: u32) -> SeededRng {
+        // the xorshift state must not be all zeros, so mix the seed into a fixed non-zero state
+        SeededRng(XorShiftRng::from_seed([0x193a6754, 0xa8a7d469 ^ seed, 0x97830e05, 0x113ba7bb]))
+    }
+}
+
+impl Rng for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_samples() {
+        let a: Vec<u32> = (0..1000).random_sample_seeded(0.1, 7).collect();
+        let b: Vec<u32> = (0..1000).random_sample_seeded(0.1, 7).collect();
+        assert_eq!(a, b);
+        assert!(a.len() > 50 && a.len() < 150);
+
+        assert_eq!((0..10).strided_sample(4).collect::<Vec<_>>(), vec![0, 4, 8]);
+
+        let a: Vec<u32> = (0..100).stratified_sample(10, SeededRng::new(3)).collect();
+        let b: Vec<u32> = (0..100).stratified_sample(10, SeededRng::new(3)).collect();
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 10);
+        assert!(a.iter().enumerate().all(|(i, &v)| v as usize / 10 == i));
+
+        // the last short block gets a sample as well, picked evenly from its items
+        let mut counts = [0; 3];
+        for seed in 0..300 {
+            let a: Vec<u32> = (0..23).stratified_sample(10, SeededRng::new(seed)).collect();
+            assert_eq!(a.len(), 3);
+            counts[a[2] as usize - 20] += 1;
         }
+        assert!(counts.iter().all(|&c| c > 70));
     }
 }
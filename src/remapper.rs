@@ -1,5 +1,5 @@
 use super::*;
-use ditherer::Ditherer;
+use ditherer::{AdaptiveStrength, Ditherer, PixelMode, PixelOptions};
 
 /// A helper type to very slightly simplify remapping images using a `Ditherer`.
 ///
@@ -8,26 +8,37 @@ use ditherer::Ditherer;
 /// `Box<Iterator<Item = u8>>`. The `_usize` functions remap to `usize` instead of `u8`,
 /// in case you need palettes with more than 256 colors.
 ///
+/// The slice based functions pass the whole image to `Ditherer::remap_image`, so ditherers
+/// needing random access to the image (like `ditherer::Riemersma`) don't have to buffer it
+/// again. The `remap_image` functions take an `ImageView` instead, which can also be a strided
+/// sub-rectangle of a larger image.
+///
+/// Both the palette and the image colors are interpreted using the `AlphaMode` of the
+/// colorspace.
+///
+/// Creating a `Remapper` for an empty palette, remapping with a width of zero or remapping to
+/// `u8` indices with more than 256 palette colors returns an `Error`.
+///
 /// # Examples
 /// ```
 /// # use exoquant::*;
 /// # let image = testdata::test_image();
 /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
 /// # let colorspace = SimpleColorSpace::default();
-/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256);
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256).unwrap();
 /// let ditherer = ditherer::FloydSteinberg::new();
-/// let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-/// let indexed_image_data = remapper.remap(&image.pixels, image.width);
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let indexed_image_data = remapper.remap(&image.pixels, image.width).unwrap();
 /// ```
 /// ```
 /// # use exoquant::*;
 /// # let image = testdata::test_image();
 /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
 /// # let colorspace = SimpleColorSpace::default();
-/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256);
+/// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256).unwrap();
 /// let ditherer = ditherer::FloydSteinberg::new();
-/// let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-/// let iter = remapper.remap_iter(Box::new(image.pixels.iter().cloned()), image.width);
+/// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+/// let iter = remapper.remap_iter(Box::new(image.pixels.iter().cloned()), image.width).unwrap();
 /// let indexed_image_data: Vec<u8> = iter.collect();
 /// ```
 pub struct Remapper<'a, T: 'a + ColorSpace, D: 'a + Ditherer + ?Sized> {
@@ -38,61 +49,560 @@ pub struct Remapper<'a, T: 'a + ColorSpace, D: 'a + Ditherer + ?Sized> {
 
 impl<'a, T: ColorSpace, D: Ditherer + ?Sized> Remapper<'a, T, D> {
     /// Create a new Remapper instance for the given palette, colorspace and ditherer.
-    pub fn new(palette: &[Color], colorspace: &'a T, ditherer: &'a D) -> Remapper<'a, T, D> {
-        Remapper {
-            map: ColorMap::new(palette, colorspace),
+    pub fn new(palette: &[Color],
+               colorspace: &'a T,
+               ditherer: &'a D)
+               -> Result<Remapper<'a, T, D>, Error> {
+        Self::from_color_map(ColorMap::new(palette, colorspace), colorspace, ditherer)
+    }
+
+    /// Create a new Remapper instance from an existing `ColorMap`.
+    ///
+    /// This allows using a `ColorMap` with non-default lookup settings, for example
+    /// `ColorMap::with_ciede2000`.
+    pub fn from_color_map(map: ColorMap,
+                          colorspace: &'a T,
+                          ditherer: &'a D)
+                          -> Result<Remapper<'a, T, D>, Error> {
+        if map.num_colors() == 0 {
+            return Err(Error::EmptyPalette);
+        }
+        Ok(Remapper {
+            map: map,
             colorspace: colorspace,
             ditherer: ditherer,
+        })
+    }
+
+    fn check_u8(&self, width: usize) -> Result<(), Error> {
+        if self.map.num_colors() > 256 {
+            return Err(Error::TooManyColors(self.map.num_colors()));
         }
+        self.check_width(width)
+    }
+
+    fn check_width(&self, width: usize) -> Result<(), Error> {
+        if width == 0 {
+            return Err(Error::ZeroWidth);
+        }
+        Ok(())
     }
 
     /// Remap and dither a `&[Color]` to a `Vec<u8>`.
-    pub fn remap(&self, image: &[Color], width: usize) -> Vec<u8> {
-        assert!(self.map.num_colors() <= 256);
-        self.ditherer
-            .remap(Box::new(image.iter().map(|&c| self.colorspace.to_float(c))),
-                   width,
-                   &self.map,
-                   self.colorspace)
+    pub fn remap(&self, image: &[Color], width: usize) -> Result<Vec<u8>, Error> {
+        self.check_u8(width)?;
+        Ok(self.remap_buffer(image, None, width)?.into_iter().map(|i| i as u8).collect())
+    }
+
+    /// Remap and dither a `&[Color]` to a `Vec<u8>` with a per-pixel dithering strength.
+    ///
+    /// `strength` holds one value per pixel, from 0.0 for no dithering to 1.0 for full
+    /// dithering. Ditherers not supporting a strength map ignore it.
+    pub fn remap_with_strength(&self,
+                               image: &[Color],
+                               width: usize,
+                               strength: &[f64])
+                               -> Result<Vec<u8>, Error> {
+        self.check_u8(width)?;
+        if strength.len() != image.len() {
+            return Err(Error::SizeMismatch);
+        }
+        Ok(self.remap_buffer(image, Some(strength), width)?
+            .into_iter()
+            .map(|i| i as u8)
+            .collect())
+    }
+
+    /// Remap and dither a `&[Color]` to a `Vec<u8>` with a per-pixel `PixelMode`.
+    ///
+    /// `modes` holds one value per pixel, see `ditherer::PixelMode`. Kept indices need to be in
+    /// the palette, otherwise `Error::InvalidIndex` is returned.
+    pub fn remap_with_modes(&self,
+                            image: &[Color],
+                            width: usize,
+                            modes: &[PixelMode])
+                            -> Result<Vec<u8>, Error> {
+        self.check_u8(width)?;
+        if modes.len() != image.len() {
+            return Err(Error::SizeMismatch);
+        }
+        let image: Vec<Colorf> = image.iter().map(|&c| self.colorspace.to_float(c)).collect();
+        let options = PixelOptions { modes: Some(modes), ..PixelOptions::default() };
+        Ok(self.remap_float(&image, options, width)?
+            .into_iter()
+            .map(|i| i as u8)
+            .collect())
+    }
+
+    /// Remap and dither a `&[Color]` to a `Vec<u8>`, adapting the dithering strength to the
+    /// local contrast of the image.
+    ///
+    /// See `ditherer::AdaptiveStrength` for details. Unlike `remap`, this needs an image made up
+    /// of whole rows, otherwise `Error::SizeMismatch` is returned.
+    pub fn remap_adaptive(&self,
+                          image: &[Color],
+                          width: usize,
+                          adaptive: &AdaptiveStrength)
+                          -> Result<Vec<u8>, Error> {
+        self.check_u8(width)?;
+        let float_image: Vec<Colorf> =
+            image.iter().map(|&c| self.colorspace.to_float(c)).collect();
+        let strength = adaptive.strength_map(&float_image, width)?;
+        let options = PixelOptions { strength: Some(&strength), ..PixelOptions::default() };
+        Ok(self.remap_float(&float_image, options, width)?
+            .into_iter()
             .map(|i| i as u8)
+            .collect())
+    }
+
+    /// Remap and dither a `&[Color]` to a `Vec<u8>` and measure the quantization error.
+    ///
+    /// The error is measured in the colorspace of this `Remapper`, both against the nearest
+    /// palette color of each pixel and against the dithered result.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// # let colorspace = SimpleColorSpace::default();
+    /// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256).unwrap();
+    /// let ditherer = ditherer::FloydSteinberg::new();
+    /// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+    /// let (indexed_image_data, stats) = remapper.remap_with_stats(&image.pixels, image.width)
+    ///   .unwrap();
+    /// println!("PSNR: {} dB, max error: {}", stats.dithered.psnr(), stats.dithered.max_error());
+    /// ```
+    pub fn remap_with_stats(&self,
+                            image: &[Color],
+                            width: usize)
+                            -> Result<(Vec<u8>, RemapStats), Error> {
+        self.check_u8(width)?;
+        let image: Vec<Colorf> = image.iter().map(|&c| self.colorspace.to_float(c)).collect();
+        let indices = self.remap_float(&image, PixelOptions::default(), width)?;
+        let mut stats = RemapStats {
+            nearest: ErrorStats::new(),
+            dithered: ErrorStats::new(),
+        };
+        for (&color, &index) in image.iter().zip(indices.iter()) {
+            let nearest = self.map.find_nearest(color);
+            stats.nearest.add(color - self.map.float_color(nearest));
+            stats.dithered.add(color - self.map.float_color(index));
+        }
+        Ok((indices.into_iter().map(|i| i as u8).collect(), stats))
+    }
+
+    /// Remap and dither a sequence of animation frames to `Vec<u8>`s.
+    ///
+    /// Pixels whose color is unchanged from the previous frame keep their previous palette
+    /// index, so static regions don't shimmer between frames and compress well with inter-frame
+    /// compression. The kept pixels pass on their actual quantization error, so only the changed
+    /// pixels are dithered against their unchanged neighbors (see `ditherer::PixelMode::Keep`).
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// # let colorspace = SimpleColorSpace::default();
+    /// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256).unwrap();
+    /// let mut second_frame = image.pixels.clone();
+    /// second_frame[0] = Color::new(255, 0, 0, 255);
+    /// let ditherer = ditherer::FloydSteinberg::new();
+    /// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+    /// let frames = remapper.remap_sequence(&[&image.pixels[..], &second_frame[..]], image.width)
+    ///   .unwrap();
+    /// ```
+    pub fn remap_sequence<F: AsRef<[Color]>>(&self,
+                                             frames: &[F],
+                                             width: usize)
+                                             -> Result<Vec<Vec<u8>>, Error> {
+        self.check_u8(width)?;
+        let mut result: Vec<Vec<u8>> = Vec::with_capacity(frames.len());
+        for (i, frame) in frames.iter().enumerate() {
+            let frame = frame.as_ref();
+            let modes: Option<Vec<PixelMode>> = if i > 0 {
+                let previous_frame = frames[i - 1].as_ref();
+                let previous = &result[i - 1];
+                Some(frame.iter()
+                    .enumerate()
+                    .map(|(j, c)| if j < previous_frame.len() && *c == previous_frame[j] {
+                        PixelMode::Keep(previous[j] as usize)
+                    } else {
+                        PixelMode::Dither
+                    })
+                    .collect())
+            } else {
+                None
+            };
+            let image: Vec<Colorf> = frame.iter().map(|&c| self.colorspace.to_float(c)).collect();
+            let options = PixelOptions {
+                modes: modes.as_ref().map(|m| &m[..]),
+                ..PixelOptions::default()
+            };
+            let indices = self.remap_float(&image, options, width)?;
+            result.push(indices.into_iter().map(|i| i as u8).collect());
+        }
+        Ok(result)
+    }
+
+    /// Returns the error of each pixel of an indexed image.
+    ///
+    /// The error is the distance between the input color and the palette color in the
+    /// colorspace of this `Remapper`. Returns `Error::SizeMismatch` if `indexed` is not as long
+    /// as `image` and `Error::InvalidIndex` for indices not in the palette.
+    pub fn error_map(&self, image: &[Color], indexed: &[u8]) -> Result<Vec<f64>, Error> {
+        if indexed.len() != image.len() {
+            return Err(Error::SizeMismatch);
+        }
+        image.iter()
+            .zip(indexed.iter())
+            .map(|(&c, &i)| {
+                let i = i as usize;
+                if i >= self.map.num_colors() {
+                    return Err(Error::InvalidIndex(i));
+                }
+                Ok((self.colorspace.to_float(c) - self.map.float_color(i)).abs())
+            })
             .collect()
     }
 
     /// Remap and dither a `&[Color]` to a `Vec<usize>`.
-    pub fn remap_usize(&self, image: &[Color], width: usize) -> Vec<usize> {
-        self.ditherer
-            .remap(Box::new(image.iter().map(|&c| self.colorspace.to_float(c))),
-                   width,
-                   &self.map,
-                   self.colorspace)
-            .collect()
+    pub fn remap_usize(&self, image: &[Color], width: usize) -> Result<Vec<usize>, Error> {
+        self.check_width(width)?;
+        self.remap_buffer(image, None, width)
+    }
+
+    /// Remap and dither an `ImageView<Color>` into a slice of `u8` indices.
+    ///
+    /// The view can cover a sub-rectangle of a larger image using a row stride. The indices
+    /// are written without padding, so `output` needs to hold exactly
+    /// `image.width() * image.height()` entries, otherwise `Error::SizeMismatch` is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// # let colorspace = SimpleColorSpace::default();
+    /// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256).unwrap();
+    /// let ditherer = ditherer::FloydSteinberg::new();
+    /// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+    /// let view = ImageView::from_slice(&image.pixels, image.width).unwrap();
+    /// let tile = view.sub_view(8, 8, 16, 16).unwrap();
+    /// let mut indexed_tile = vec![0u8; 16 * 16];
+    /// remapper.remap_image(&tile, &mut indexed_tile).unwrap();
+    /// ```
+    pub fn remap_image(&self, image: &ImageView<Color>, output: &mut [u8]) -> Result<(), Error> {
+        self.check_u8(image.width())?;
+        let mut indices = vec![0; output.len()];
+        self.remap_image_usize(image, &mut indices)?;
+        for (o, i) in output.iter_mut().zip(indices) {
+            *o = i as u8;
+        }
+        Ok(())
+    }
+
+    /// Remap and dither an `ImageView<Color>` into a slice of `usize` indices.
+    pub fn remap_image_usize(&self,
+                             image: &ImageView<Color>,
+                             output: &mut [usize])
+                             -> Result<(), Error> {
+        self.check_width(image.width())?;
+        if output.len() != image.width() * image.height() {
+            return Err(Error::SizeMismatch);
+        }
+        let float_image: Vec<Colorf> =
+            image.pixels().map(|c| self.colorspace.to_float(c)).collect();
+        let view = ImageView::from_slice(&float_image, image.width())?;
+        let options = PixelOptions::default();
+        self.ditherer.remap_image(&view, options, &self.map, self.colorspace, output)
+    }
+
+    fn remap_buffer(&self,
+                    image: &[Color],
+                    strength: Option<&[f64]>,
+                    width: usize)
+                    -> Result<Vec<usize>, Error> {
+        let image: Vec<Colorf> = image.iter().map(|&c| self.colorspace.to_float(c)).collect();
+        let options = PixelOptions { strength: strength, ..PixelOptions::default() };
+        self.remap_float(&image, options, width)
+    }
+
+    // Remaps a complete image through `Ditherer::remap_image`, giving the ditherer random
+    // access to all pixels. A partial last row is padded with skipped pixels for the ditherer
+    // and cut off again.
+    fn remap_float(&self,
+                   image: &[Colorf],
+                   options: PixelOptions,
+                   width: usize)
+                   -> Result<Vec<usize>, Error> {
+        let padded_len = image.len() + (width - image.len() % width) % width;
+        let mut output = vec![0; padded_len];
+        if padded_len == image.len() {
+            let view = ImageView::from_slice(image, width)?;
+            self.ditherer.remap_image(&view, options, &self.map, self.colorspace, &mut output)?;
+        } else {
+            let mut padded = image.to_vec();
+            padded.resize(padded_len, Colorf::zero());
+            let strength = options.strength.map(|s| {
+                let mut s = s.to_vec();
+                s.resize(padded_len, 0.0);
+                s
+            });
+            let modes = padded_modes(options.modes, image.len(), padded_len);
+            let view = ImageView::from_slice(&padded, width)?;
+            let options = PixelOptions {
+                strength: strength.as_ref().map(|s| &s[..]),
+                modes: Some(&modes),
+            };
+            self.ditherer.remap_image(&view, options, &self.map, self.colorspace, &mut output)?;
+        }
+        output.truncate(image.len());
+        Ok(output)
     }
 
     /// Remap and dither a `Box<Iterator<Item = Color>>` to a `Box<Iterator<Item = u8>>`.
     pub fn remap_iter<'b>(&'b self,
                           image: Box<Iterator<Item = Color> + 'b>,
                           width: usize)
-                          -> Box<Iterator<Item = u8> + 'b> {
-        assert!(self.map.num_colors() <= 256);
-        Box::new(self.ditherer
+                          -> Result<Box<Iterator<Item = u8> + 'b>, Error> {
+        self.check_u8(width)?;
+        Ok(Box::new(self.ditherer
             .remap(Box::new(image.map(move |c| self.colorspace.to_float(c))),
                    width,
                    &self.map,
                    self.colorspace)
-            .map(|i| i as u8))
+            .map(|i| i as u8)))
     }
 
     /// Remap and dither a `Box<Iterator<Item = Color>>` to a `Box<Iterator<Item = usize>>`.
     pub fn remap_iter_usize<'b>(&'b self,
                                 image: Box<Iterator<Item = Color> + 'b>,
                                 width: usize)
-                                -> Box<Iterator<Item = usize> + 'b> {
-        assert!(self.map.num_colors() <= 256);
-        self.ditherer
+                                -> Result<Box<Iterator<Item = usize> + 'b>, Error> {
+        self.check_width(width)?;
+        Ok(self.ditherer
             .remap(Box::new(image.map(move |c| self.colorspace.to_float(c))),
                    width,
                    &self.map,
-                   self.colorspace)
+                   self.colorspace))
+    }
+}
+
+// Returns the pixel modes of an image of `len` pixels padded to `padded_len` pixels, skipping
+// the padding.
+fn padded_modes(modes: Option<&[PixelMode]>, len: usize, padded_len: usize) -> Vec<PixelMode> {
+    let mut modes = modes.map_or_else(|| vec![PixelMode::Dither; len], |m| m.to_vec());
+    modes.resize(padded_len, PixelMode::Skip);
+    modes
+}
+
+#[cfg(feature="parallel")]
+impl<'a, T: ColorSpace + Sync, D: Ditherer + Sync + ?Sized> Remapper<'a, T, D> {
+    /// Remap and dither a `&[Color]` to a `Vec<u8>` using multiple threads.
+    ///
+    /// The image is split into horizontal bands which are remapped in parallel, each starting
+    /// with the `Ditherer::band_context` rows above it to hide the seams between bands. The
+    /// result is identical to `remap` for ditherers that work on each pixel independently, like
+    /// `ditherer::Ordered`, and very close for error diffusion. Ditherers that can't be split
+    /// into bands, like `ditherer::Riemersma`, are run on a single thread.
+    ///
+    /// Only available with the `parallel` feature.
+    ///
+    /// # Examples
+    /// ```
+    /// # use exoquant::*;
+    /// # let image = testdata::test_image();
+    /// # let histogram: Histogram = image.pixels.iter().cloned().collect();
+    /// # let colorspace = SimpleColorSpace::default();
+    /// # let palette = generate_palette(&histogram, &colorspace, &optimizer::None, 256).unwrap();
+    /// let ditherer = ditherer::FloydSteinberg::new();
+    /// let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+    /// let indexed_image_data = remapper.remap_parallel(&image.pixels, image.width).unwrap();
+    /// ```
+    pub fn remap_parallel(&self, image: &[Color], width: usize) -> Result<Vec<u8>, Error> {
+        self.check_u8(width)?;
+        Ok(self.remap_bands(image, width)?.into_iter().map(|i| i as u8).collect())
+    }
+
+    /// Remap and dither a `&[Color]` to a `Vec<usize>` using multiple threads.
+    ///
+    /// See `remap_parallel`.
+    pub fn remap_parallel_usize(&self, image: &[Color], width: usize) -> Result<Vec<usize>, Error> {
+        self.check_width(width)?;
+        self.remap_bands(image, width)
+    }
+
+    fn remap_bands(&self, image: &[Color], width: usize) -> Result<Vec<usize>, Error> {
+        use rayon::prelude::*;
+
+        let mut float_image: Vec<Colorf> =
+            image.par_iter().map(|&c| self.colorspace.to_float(c)).collect();
+        let context = match self.ditherer.band_context() {
+            Some(context) => context,
+            None => return self.remap_float(&float_image, PixelOptions::default(), width),
+        };
+        let band_rows = (context * 4).max(64);
+        let padded_len = image.len() + (width - image.len() % width) % width;
+        float_image.resize(padded_len, Colorf::zero());
+        let modes = padded_modes(None, image.len(), padded_len);
+        let mut output = vec![0; padded_len];
+        let view = ImageView::from_slice(&float_image, width)?;
+        output.par_chunks_mut(band_rows * width).enumerate().try_for_each(|(band, output)| {
+            let first_row = band * band_rows;
+            let start = first_row.saturating_sub(context);
+            let rows = first_row + output.len() / width - start;
+            let mut indices = vec![0; rows * width];
+            let options = PixelOptions {
+                modes: Some(&modes[start * width..(start + rows) * width]),
+                ..PixelOptions::default()
+            };
+            self.ditherer.remap_band(&view.sub_view(0, start, width, rows)?,
+                                     start,
+                                     options,
+                                     &self.map,
+                                     self.colorspace,
+                                     &mut indices)?;
+            output.copy_from_slice(&indices[(first_row - start) * width..]);
+            Ok(())
+        })?;
+        output.truncate(image.len());
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn remap_image_matches_cropped_copy() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let ditherer = ditherer::ErrorDiffusion::stucki();
+        let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+        let texture: Vec<Color> = (0..32 * 32)
+            .map(|i| Color::new((i % 32 * 8) as u8, (i / 32 * 8) as u8, 100, 255))
+            .collect();
+        let view = ImageView::new(&texture, 32, 32, 32).unwrap().sub_view(5, 7, 10, 12).unwrap();
+        let mut indices = vec![0; 10 * 12];
+        remapper.remap_image(&view, &mut indices).unwrap();
+        assert_eq!(indices, remapper.remap(&view.pixels().collect::<Vec<_>>(), 10).unwrap());
+        assert_eq!(remapper.remap_image(&view, &mut [0; 10]), Err(Error::SizeMismatch));
+    }
+
+    #[test]
+    fn remap_skips_padding_of_partial_row() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        // the partial last row is an odd one, so serpentine scanning starts at the padding
+        let ditherer = ditherer::ErrorDiffusion::floyd_steinberg().serpentine();
+        let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+        let image: Vec<Color> =
+            (0..30).map(|i| Color::new(160 + (i % 3) as u8 * 5, 160, 160, 255)).collect();
+        let unpadded: Vec<u8> = remapper.remap_iter(Box::new(image.iter().cloned()), 8)
+            .unwrap()
+            .collect();
+        assert_eq!(remapper.remap(&image, 8).unwrap(), unpadded);
+    }
+
+    #[cfg(feature="parallel")]
+    #[test]
+    fn remap_parallel_matches_sequential() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let image: Vec<Color> = (0..100 * 301)
+            .map(|i| Color::new((i % 100 * 2) as u8, (i / 100 % 256) as u8, 100, 255))
+            .collect();
+        let ordered = ditherer::OrderedMatrix::bayer(8).unwrap();
+        let remapper = Remapper::new(&palette, &colorspace, &ordered).unwrap();
+        assert_eq!(remapper.remap_parallel(&image, 100).unwrap(),
+                   remapper.remap(&image, 100).unwrap());
+        let floyd_steinberg = ditherer::FloydSteinberg::new();
+        let remapper = Remapper::new(&palette, &colorspace, &floyd_steinberg).unwrap();
+        let parallel = remapper.remap_parallel(&image, 100).unwrap();
+        let sequential = remapper.remap(&image, 100).unwrap();
+        assert_eq!(parallel.len(), sequential.len());
+        assert_eq!(parallel[..64 * 100], sequential[..64 * 100]);
+        // the dither patterns drift apart after the first band, but the error over the whole
+        // image and the local brightness stay the same
+        let mean_error = |indices: &[u8]| {
+            remapper.error_map(&image, indices).unwrap().iter().sum::<f64>() / image.len() as f64
+        };
+        assert!((mean_error(&parallel) / mean_error(&sequential) - 1.0).abs() < 0.005);
+        let block_sum = |indices: &[u8], bx: usize, by: usize| {
+            (0..100)
+                .map(|i| indices[bx * 10 + i % 10 + (by * 10 + i / 10) * 100] as i32)
+                .sum::<i32>()
+        };
+        for by in 0..30 {
+            for bx in 0..10 {
+                assert!((block_sum(&parallel, bx, by) - block_sum(&sequential, bx, by)).abs() <= 5);
+            }
+        }
+    }
+
+    #[test]
+    fn remap_sequence_keeps_static_pixels() {
+        let colorspace = SimpleColorSpace::default();
+        let palette = [Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        let ditherer = ditherer::FloydSteinberg::vanilla();
+        let remapper = Remapper::new(&palette, &colorspace, &ditherer).unwrap();
+        let gray = Color::new(128, 128, 128, 255);
+        let first: Vec<Color> = (0..64).map(|_| gray).collect();
+        let mut second = first.clone();
+        for c in &mut second[..8] {
+            *c = Color::new(255, 255, 255, 255);
+        }
+        second[63] = Color::new(140, 140, 140, 255);
+        let frames = remapper.remap_sequence(&[first.clone(), second.clone()], 8).unwrap();
+        assert_eq!(frames[0], remapper.remap(&first, 8).unwrap());
+        assert!(frames[1][..8].iter().all(|&i| i == 1));
+        assert_eq!(frames[1][8..63], frames[0][8..63]);
+
+        // the last pixel only gets the errors of its kept neighbors, not the ones of a fresh
+        // dithering of the second frame
+        let map = ColorMap::new(&palette, &colorspace);
+        let error = |i: usize| {
+            colorspace.to_dither(colorspace.to_float(gray)) -
+            colorspace.to_dither(map.float_color(frames[0][i] as usize))
+        };
+        let incoming = error(62) * (7.0 / 16.0) + error(55) * (5.0 / 16.0) +
+                       error(54) * (1.0 / 16.0);
+        let c = colorspace.to_dither(colorspace.to_float(second[63])) + incoming;
+        let expected = map.find_nearest(colorspace.from_dither(c)) as u8;
+        assert_eq!(frames[1][63], expected);
+        assert!(remapper.remap(&second, 8).unwrap()[63] != expected);
+    }
+
+    #[test]
+    fn remap_with_stats_measures_error() {
+        let colorspace = SimpleColorSpace::default();
+        let (black, white) = (Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255));
+        let remapper = Remapper::new(&[black, white], &colorspace, &ditherer::None).unwrap();
+
+        let (indices, stats) = remapper.remap_with_stats(&[black, white, white, black], 2)
+            .unwrap();
+        assert_eq!(indices, vec![0, 1, 1, 0]);
+        assert_eq!(stats.dithered.count(), 4);
+        assert_eq!(stats.dithered.mse(), 0.0);
+        assert_eq!(stats.dithered.psnr(), f64::INFINITY);
+        assert_eq!(stats.nearest.max_error(), 0.0);
+        assert_eq!(remapper.error_map(&[black, white], &[0, 1]).unwrap(), vec![0.0, 0.0]);
 
+        // a dark gray maps to black with the difference of their float colors as error
+        let gray = Color::new(64, 64, 64, 255);
+        let diff = colorspace.to_float(gray) - colorspace.to_float(black);
+        let (indices, stats) = remapper.remap_with_stats(&[gray, black], 2).unwrap();
+        assert_eq!(indices, vec![0, 0]);
+        let mse = (diff.r * diff.r + diff.g * diff.g + diff.b * diff.b) / 8.0;
+        assert!((stats.dithered.mse() - mse).abs() < 1e-12);
+        assert!((stats.dithered.psnr() + 10.0 * mse.log10()).abs() < 1e-9);
+        assert!((stats.dithered.max_error() - diff.abs()).abs() < 1e-12);
+        assert!((stats.nearest.mse() - mse).abs() < 1e-12);
+        assert_eq!(remapper.error_map(&[gray, black], &indices).unwrap(), vec![diff.abs(), 0.0]);
+        assert_eq!(remapper.error_map(&[gray], &[0, 0]), Err(Error::SizeMismatch));
+        assert_eq!(remapper.error_map(&[gray], &[2]), Err(Error::InvalidIndex(2)));
     }
 }
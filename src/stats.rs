@@ -0,0 +1,128 @@
+use super::*;
+
+/// Quantization error statistics, measured in quantization colorspace.
+///
+/// The per channel values are returned as `Colorf`s. The mean squared error and the PSNR
+/// (with a peak value of 1.0) are calculated the same way as in `Quantizer::mse` and
+/// `Quantizer::psnr`.
+#[derive(Clone, Debug)]
+pub struct ErrorStats {
+    sum: Colorf,
+    max: Colorf,
+    max_distance: f64,
+    count: usize,
+}
+
+impl Default for ErrorStats {
+    fn default() -> ErrorStats {
+        ErrorStats::new()
+    }
+}
+
+impl ErrorStats {
+    /// Returns a new, empty `ErrorStats`.
+    pub fn new() -> ErrorStats {
+        ErrorStats {
+            sum: Colorf::zero(),
+            max: Colorf::zero(),
+            max_distance: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Adds the difference between an input color and the color representing it.
+    pub fn add(&mut self, diff: Colorf) {
+        self.sum += diff * diff;
+        self.max.r = self.max.r.max(diff.r.abs());
+        self.max.g = self.max.g.max(diff.g.abs());
+        self.max.b = self.max.b.max(diff.b.abs());
+        self.max.a = self.max.a.max(diff.a.abs());
+        self.max_distance = self.max_distance.max(diff.abs());
+        self.count += 1;
+    }
+
+    /// Returns the number of pixels measured.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the mean squared error of each channel.
+    pub fn channel_mse(&self) -> Colorf {
+        self.sum * (1.0 / self.count.max(1) as f64)
+    }
+
+    /// Returns the mean squared error over all channels.
+    pub fn mse(&self) -> f64 {
+        let mse = self.channel_mse();
+        (mse.r + mse.g + mse.b + mse.a) / 4.0
+    }
+
+    /// Returns the peak signal-to-noise ratio in dB of each channel.
+    pub fn channel_psnr(&self) -> Colorf {
+        let mse = self.channel_mse();
+        Colorf {
+            r: psnr(mse.r),
+            g: psnr(mse.g),
+            b: psnr(mse.b),
+            a: psnr(mse.a),
+        }
+    }
+
+    /// Returns the peak signal-to-noise ratio in dB over all channels.
+    pub fn psnr(&self) -> f64 {
+        psnr(self.mse())
+    }
+
+    /// Returns the maximum absolute error of each channel.
+    pub fn channel_max_error(&self) -> Colorf {
+        self.max
+    }
+
+    /// Returns the maximum distance between an input color and the color representing it.
+    pub fn max_error(&self) -> f64 {
+        self.max_distance
+    }
+}
+
+fn psnr(mse: f64) -> f64 {
+    -10.0 * mse.log10()
+}
+
+/// The error statistics of a remapped image, as returned by `Remapper::remap_with_stats`.
+#[derive(Clone, Debug)]
+pub struct RemapStats {
+    /// The error when mapping each pixel to its nearest palette color, without dithering.
+    pub nearest: ErrorStats,
+    /// The error of the actual, dithered result.
+    pub dithered: ErrorStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn error_stats() {
+        let mut stats = ErrorStats::new();
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.mse(), 0.0);
+        assert_eq!(stats.psnr(), f64::INFINITY);
+        assert_eq!(stats.max_error(), 0.0);
+
+        stats.add(Colorf {
+            r: 0.3,
+            g: -0.4,
+            b: 0.0,
+            a: 0.0,
+        });
+        stats.add(Colorf::zero());
+        assert_eq!(stats.count(), 2);
+        // (0.09 + 0.16) / 2 pixels / 4 channels
+        assert!((stats.mse() - 0.03125).abs() < 1e-12);
+        assert!((stats.channel_mse().g - 0.08).abs() < 1e-12);
+        assert!((stats.psnr() - 15.0515).abs() < 1e-4);
+        assert!((stats.channel_max_error().g - 0.4).abs() < 1e-12);
+        assert!((stats.max_error() - 0.5).abs() < 1e-12);
+        assert_eq!(stats.channel_psnr().b, f64::INFINITY);
+    }
+}